@@ -1,38 +1,140 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::fs;
+use std::fs::DirBuilder;
 use std::fs::DirEntry;
 use std::fs::File;
+use std::fs::FileTimes;
 use std::fs::OpenOptions;
 use std::fs::Permissions;
+use std::fs::TryLockError;
 use std::fs::read_dir;
 use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::io::ErrorKind;
+use std::io::IsTerminal;
 use std::io::Read;
+use std::io::Write;
+use std::os::unix;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::fs::PermissionsExt;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::exit;
 use std::str::FromStr;
 use std::string::ParseError;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use flate2::read::GzDecoder;
+use glob::Pattern;
 use ini::Ini;
+use serde::Serialize;
+use tar::Archive;
+use tar::EntryType;
+
+use crate::netctl::parse_network;
+use crate::netctl::parse_profile;
+use crate::netctl::MAX_SSID_LENGTH;
+use crate::netctl::ParseOptions;
+use crate::netctl::Profile;
+use crate::netctl::QuotingError;
+use crate::netctl::Warning;
 
 use crate::networks::Network;
 use crate::networks::PSKSecurity;
 use crate::networks::Security;
+use crate::networks::WriteOptions;
+use crate::stream;
+
+/// Output directory value that streams the generated files to stdout instead.
+pub const STREAM_OUTPUT: &str = "-";
+
+/// Input file name that reads the profile from stdin.
+pub const STDIN_INPUT: &str = "-";
+
+/// Default permissions for generated iwd files.
+pub const OUTPUT_MODE: u32 = 0o600;
+
+/// Permissions for generated directories.
+const DIR_MODE: u32 = 0o700;
+
+/// Subdirectory of the output directory holding secrets in the split layout.
+pub const SECRETS_DIR: &str = "secrets";
+
+/// Name of the lock file in the output directory that serializes concurrent runs.
+pub const LOCK_FILE: &str = ".netctl2iwd.lock";
+
+/// The first bytes of a gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 #[derive(Debug)]
 pub enum ConversionError {
-    ParseError(String),
+    /// A description of the problem, and the error that caused it, if any.
+    ParseError(String, Option<Box<dyn Error + Send + Sync>>),
+    /// The profile has no keys, other than under section headers, so it is not a netctl profile.
+    EmptyProfile,
+    /// A profile for another interface than the one to convert for, if it has one at all.
+    OtherInterface(Option<String>),
+    /// A profile whose networks all have another security type than `Options::only_security`.
+    OtherSecurity(&'static str),
+    /// A profile that isn't valid UTF-8, which is what netctl profiles are expected to be.
+    InvalidUtf8,
     NotWireless,
+    /// A `Connection=ethernet` profile, which iwd doesn't manage.
+    Wired,
     MissingKeys,
+    EmptyKey(String),
     MissingSSID,
+    /// A profile with a `Key` but without `Security`, without `ParseOptions::assume_security`.
+    MissingSecurity,
+    /// An SSID that is empty or longer than 32 bytes.
+    InvalidSSID(String),
+    InvalidPassphrase,
+    /// A raw PSK that isn't 64 hexadecimal digits.
+    InvalidPSK,
     Unsupported,
-    PermissionDenied,
-    FileExists,
-    OSError,
+    UnsupportedWEP,
+    PermissionDenied(io::Error),
+    OutputPermissionDenied(PathBuf),
+    ReadOnlyFilesystem(io::Error),
+    FileExists(io::Error),
+    SharedRawPSK,
+    /// A network to rename with `Options::remap`, from this SSID to that one, has only a
+    /// precomputed PSK, which is derived from the old SSID.
+    RemappedRawPSK(String, String),
+    ProfileNotFound(PathBuf),
+    /// A directory was given where a profile was expected.
+    IsDirectory(PathBuf),
+    NoMatches(String),
+    InvalidIpConfig(String),
+    UnsetVariable(String),
+    MalformedQuoting(String, QuotingError),
+    Strict(Warning),
+    UnreadableCertificate(PathBuf, io::Error),
+    OwnershipDenied(PathBuf),
+    /// The output directory doesn't exist, and wasn't to be created.
+    MissingOutputDir(PathBuf),
+    /// The iwd configuration would be less strict about security than the profile, for the SSID
+    /// and the reasons given. See `Network::downgrades`.
+    Downgrade(String, Vec<String>),
+    /// A generated file name that would end up outside of the output directory.
+    OutsideOutputDir(PathBuf),
+    /// The profile converted, but writing the output stream failed.
+    StreamError(io::Error),
+    OSError(io::Error),
 }
 
 impl Display for ConversionError {
@@ -40,37 +142,378 @@ impl Display for ConversionError {
         use self::ConversionError::*;
 
         match self {
-            ParseError(value) => write!(f, "Unable to parse profile: {}", value),
+            ParseError(value, _) => write!(f, "Unable to parse profile: {}", value),
+            EmptyProfile => write!(f, "Empty or malformed profile, without any keys outside of a section"),
+            OtherInterface(Some(interface)) => write!(f, "Profile is for interface {}", interface),
+            OtherInterface(None) => write!(f, "Profile has no interface"),
+            OtherSecurity(security) => write!(f, "Profile is for a {} network, which isn't converted in this run", security),
+            InvalidUtf8 => write!(f, "Profile is not valid UTF-8 text; re-encode it, or give the ESSID in hex as ESSID=\\\"<hex>"),
             NotWireless => write!(f, "Not a wireless profile"),
+            Wired => write!(f, "Wired (ethernet) profile, which iwd doesn't manage; keep it in netctl, or move it to systemd-networkd"),
             MissingKeys => write!(f, "Key information missing"),
+            EmptyKey(key) => write!(f, "{} is empty", key),
             MissingSSID => write!(f, "SSID missing"),
+            MissingSecurity => write!(f, "The profile has a Key but no Security; add Security=wpa, or pass --assume-security"),
+            InvalidSSID(ssid) => write!(f, "SSID must be between 1 and 32 bytes: {:?}", ssid),
+            InvalidPassphrase => write!(f, "Passphrase must be between 8 and 63 characters"),
+            InvalidPSK => write!(f, "Raw PSK must be 64 hexadecimal digits"),
             Unsupported => write!(f, "Unsupported security type"),
-            PermissionDenied => write!(f, "Permission denied"),
-            FileExists => write!(f, "File exists, refusing to overwrite"),
-            OSError => write!(f, "Unknown error"),
+            UnsupportedWEP => write!(f, "WEP is not supported by iwd; consider upgrading the network to WPA2"),
+            PermissionDenied(_) => write!(f, "Permission denied"),
+            OutputPermissionDenied(path) => write!(f, "Permission denied writing {}; run as root or choose a writable --output-dir", path.display()),
+            ReadOnlyFilesystem(_) => write!(f, "Read-only file system; remount it read-write or choose a writable --output-dir"),
+            FileExists(_) => write!(f, "File exists, refusing to overwrite"),
+            SharedRawPSK => write!(f, "A precomputed key cannot be shared between multiple SSIDs"),
+            RemappedRawPSK(from, to) => write!(f, "Cannot rename {} to {}, because its precomputed key only works for the old SSID; give the passphrase instead", from, to),
+            ProfileNotFound(path) => write!(f, "No such profile: {}", path.display()),
+            IsDirectory(_) => write!(f, "Expected a file, got a directory; did you mean --input-dir?"),
+            NoMatches(pattern) => write!(f, "No files match {}", pattern),
+            InvalidIpConfig(message) => write!(f, "Invalid IP configuration: {}", message),
+            UnsetVariable(name) => write!(f, "Environment variable {} referenced by Key is not set", name),
+            MalformedQuoting(key, error) => write!(f, "Malformed quoting in {}: {}", key, error),
+            Strict(warning) => write!(f, "{} (refusing because of --strict)", warning),
+            UnreadableCertificate(path, error) => write!(f, "Unable to read certificate {}: {}", path.display(), error),
+            OwnershipDenied(path) => write!(f, "Not permitted to change the owner of {}; run as root or drop --owner", path.display()),
+            MissingOutputDir(path) => write!(f, "Output directory {} does not exist; create it or pass --mkdir", path.display()),
+            Downgrade(ssid, reasons) => write!(f, "Converting {} would weaken its security: {}; pass --allow-downgrade to convert it anyway", ssid, reasons.join(", ")),
+            OutsideOutputDir(path) => write!(f, "Refusing to write {}, which is outside of the output directory", path.display()),
+            StreamError(error) => write!(f, "Failed to write output stream: {}", error),
+            OSError(_) => write!(f, "Unknown error"),
+        }
+    }
+}
+
+impl Error for ConversionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::ConversionError::*;
+
+        match self {
+            ParseError(_, Some(source)) => Some(source.as_ref()),
+            PermissionDenied(source) | ReadOnlyFilesystem(source) | FileExists(source) | OSError(source) => Some(source),
+            UnreadableCertificate(_, source) | StreamError(source) => Some(source),
+            _ => None,
         }
     }
 }
 
 impl From<ini::ini::Error> for ConversionError {
     fn from(ini_error: ini::ini::Error) -> Self {
-        ConversionError::ParseError(ini_error.to_string())
+        match ini_error {
+            // Reading the text is the only place where this kind of error comes from.
+            ini::ini::Error::Io(e) if e.kind() == ErrorKind::InvalidData => ConversionError::InvalidUtf8,
+            _ => ConversionError::ParseError(ini_error.to_string(), Some(Box::new(ini_error))),
+        }
+    }
+}
+
+impl From<ini::ini::ParseError> for ConversionError {
+    fn from(parse_error: ini::ini::ParseError) -> Self {
+        ConversionError::ParseError(parse_error.to_string(), Some(Box::new(parse_error)))
     }
 }
 
 impl From<io::Error> for ConversionError {
     fn from(io_error: io::Error) -> Self {
         match io_error.kind() {
-            ErrorKind::PermissionDenied => ConversionError::PermissionDenied,
-            ErrorKind::AlreadyExists => ConversionError::FileExists,
-            _ => ConversionError::OSError,
+            ErrorKind::PermissionDenied => ConversionError::PermissionDenied(io_error),
+            ErrorKind::AlreadyExists => ConversionError::FileExists(io_error),
+            ErrorKind::ReadOnlyFilesystem => ConversionError::ReadOnlyFilesystem(io_error),
+            _ => ConversionError::OSError(io_error),
         }
     }
 }
 
+/// Convert an error writing to the output directory, naming the path on permission problems.
+fn output_error(io_error: io::Error, path: &Path) -> ConversionError {
+    if io_error.kind() == ErrorKind::PermissionDenied {
+        ConversionError::OutputPermissionDenied(path.to_owned())
+    } else {
+        ConversionError::from(io_error)
+    }
+}
+
 impl From<ParseError> for ConversionError {
-    fn from(_: ParseError) -> Self {
-        ConversionError::OSError
+    fn from(parse_error: ParseError) -> Self {
+        // Parsing a path never fails.
+        match parse_error {}
+    }
+}
+
+/// Options controlling how profiles are converted.
+#[derive(Default)]
+pub struct Options {
+    /// Options for parsing the profiles.
+    pub parse: ParseOptions,
+    /// Options for writing the network itself.
+    pub write: WriteOptions,
+    /// Start each file with a comment noting how and when it was generated.
+    pub stamp: bool,
+    /// Settings merged into each file before the network-specific ones.
+    pub base_settings: BaseSettings,
+    /// How generated files are laid out on disk.
+    pub layout: Layout,
+    /// Write the files of each network to a subdirectory named after its security type, like `psk/`.
+    pub split_by_security: bool,
+    /// Order in which files are streamed to stdout.
+    pub sort: SortKey,
+    /// Only count how many profiles would convert with `count_files`, instead of converting them.
+    pub count: bool,
+    /// Explain open networks with a comment, instead of leaving their files empty.
+    pub annotate_open: bool,
+    /// Print the files that would be written to stdout, instead of writing them.
+    pub dry_run: bool,
+    /// Replace existing files instead of refusing to convert.
+    pub force: bool,
+    /// Ask on the terminal before replacing each existing file, if stdin is a terminal.
+    pub interactive: bool,
+    /// Keep replaced files as backups, with `force` or `interactive`.
+    pub backup: bool,
+    /// Write the configurations to stdout, each preceded by a comment naming it, instead of to files.
+    pub stdout: bool,
+    /// Also convert profiles in subdirectories of an input directory.
+    pub recursive: bool,
+    /// Don't report profiles that were converted successfully.
+    pub quiet: bool,
+    /// Show how many profiles have been processed on stderr, if it is a terminal.
+    pub progress: bool,
+    /// Level of detail about each converted profile: 1 for the keys read and the files written, 2
+    /// to also include the parsed networks, with their secrets redacted.
+    pub verbose: u8,
+    /// How the results of the conversion are reported.
+    pub format: OutputFormat,
+    /// Maximum number of profiles converted at the same time, or 0 for one per CPU.
+    pub jobs: usize,
+    /// Copy the certificates and keys of enterprise networks to the output directory, whose
+    /// absolute path this is, and refer to the copies instead.
+    pub cert_dir: Option<PathBuf>,
+    /// Permissions for written files, instead of `OUTPUT_MODE`.
+    pub mode: Option<u32>,
+    /// Owner for written files, instead of the user running the conversion.
+    pub owner: Option<Owner>,
+    /// Only convert profiles for this interface, skipping the others.
+    pub interface: Option<String>,
+    /// Name of the iwd file to write, instead of the one derived from the SSID. The extension for
+    /// the security type is added if it's missing.
+    pub output_name: Option<String>,
+    /// Appended to the names of the iwd files after the extension, like `.new`, so iwd ignores them.
+    pub output_suffix: Option<String>,
+    /// Skip the profiles in input directories whose file names match any of these patterns.
+    pub exclude: Vec<Pattern>,
+    /// Only convert the networks with this iwd security type, like `open` or `psk`.
+    pub only_security: Option<String>,
+    /// Give written files the access and modification times of their profiles.
+    pub preserve_times: bool,
+    /// Merge the generated configurations into existing files, instead of refusing to replace them.
+    pub merge: bool,
+    /// New SSIDs by old SSID, for networks to convert under another name.
+    pub remap: HashMap<String, String>,
+    /// Convert networks whose iwd configuration is less strict than the profile, with a warning,
+    /// instead of refusing.
+    pub allow_downgrade: bool,
+    /// Leave out the files that an earlier profile already generated, with a warning, instead of
+    /// only warning about collisions. For runs over several input directories.
+    pub dedupe: bool,
+    /// Where to record what happened to each profile, besides reporting the results.
+    pub log: Option<Log>,
+}
+
+/// An append-only log of a run, with a timestamped line for each step of converting a profile.
+///
+/// Every line is written as soon as it's logged, so an interrupted run still leaves a trail.
+pub struct Log {
+    file: File,
+}
+
+impl Log {
+    /// Open the log at `path`, adding to what earlier runs logged.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).mode(OUTPUT_MODE).open(path)?;
+        Ok(Log { file })
+    }
+
+    /// Log a line about a profile.
+    ///
+    /// Failing to log is reported, but doesn't stop the conversion.
+    pub fn entry(&self, source: &str, message: impl Display) {
+        let line = format!("{} {}: {}\n", now(), source, message);
+        // A single write per line, so lines from concurrent runs aren't interleaved.
+        if let Err(e) = (&self.file).write_all(line.as_bytes()).and_then(|_| (&self.file).flush()) {
+            eprintln!("Warning: failed to write to the log: {}", e);
+        }
+    }
+
+    /// Log how converting a profile ended, after the files it wrote.
+    fn result(&self, source: &str, result: &Result<Converted, ConversionError>) {
+        match result {
+            Ok(converted) => {
+                for warning in &converted.warnings {
+                    self.entry(source, format_args!("warning: {}", warning));
+                }
+                self.entry(source, "converted");
+            }
+            Err(e @ ConversionError::FileExists(_)) | Err(e @ ConversionError::OtherSecurity(_)) => self.entry(source, format_args!("skipped: {}", e)),
+            Err(e) => self.entry(source, format_args!("failed: {}", e)),
+        }
+    }
+}
+
+/// User and group to own the written files, either of which may be left unchanged.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FromStr for Owner {
+    type Err = String;
+
+    /// Parse `user`, `user:group` or `:group`, where both are names or numeric ids.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+
+        let lookup = |name: &str, database: &str| match name.parse::<u32>() {
+            Ok(id) => Ok(id),
+            Err(_) => lookup_field(Path::new(database), name, 2)
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(|| format!("No such user or group: {}", name)),
+        };
+
+        let uid = Some(user).filter(|user| !user.is_empty()).map(|user| lookup(user, "/etc/passwd")).transpose()?;
+        let gid = group.filter(|group| !group.is_empty()).map(|group| lookup(group, "/etc/group")).transpose()?;
+        if uid.is_none() && gid.is_none() {
+            return Err(format!("Invalid owner: {}", s));
+        }
+
+        Ok(Owner { uid, gid })
+    }
+}
+
+/// Find a field of the entry for a name in `/etc/passwd` or `/etc/group`, which share the same layout.
+///
+/// The id is field 2, and the home directory of a user field 5.
+fn lookup_field(database: &Path, name: &str, field: usize) -> Option<String> {
+    let contents = fs::read_to_string(database).ok()?;
+
+    contents.lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&name))
+        .and_then(|fields| Some(fields.get(field)?.to_string()))
+}
+
+/// Format of the conversion report.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum OutputFormat {
+    /// A line per profile, followed by a summary.
+    #[default]
+    Text,
+    /// A JSON array of records, printed at the end of the run.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+/// Ordering of streamed output files.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum SortKey {
+    /// By iwd file name.
+    #[default]
+    Name,
+    Ssid,
+    /// By security type, as named by `Security::type_name`.
+    Security,
+    /// By the profile the file was generated from.
+    Source,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "ssid" => Ok(SortKey::Ssid),
+            "security" => Ok(SortKey::Security),
+            "source" => Ok(SortKey::Source),
+            _ => Err(format!("Invalid sort key: {}", s)),
+        }
+    }
+}
+
+/// A generated file, before it is written.
+#[derive(Debug)]
+pub struct OutputFile {
+    /// Path relative to the output directory.
+    pub name: String,
+    pub contents: Vec<u8>,
+    pub ssid: String,
+    pub security: &'static str,
+    /// The profile this file was generated from.
+    pub source: String,
+    /// Whether this is a private key or a file of `SECRETS_DIR`, which is always written with
+    /// `OUTPUT_MODE`.
+    pub secret: bool,
+}
+
+/// Storage layout of the generated files.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum Layout {
+    /// A single file per network, as read by iwd.
+    #[default]
+    Classic,
+    /// The `[Security]` section is moved to a file with the same name in `SECRETS_DIR`, for
+    /// setups that provision secrets separately from the other settings.
+    Split,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "classic" => Ok(Layout::Classic),
+            "split" => Ok(Layout::Split),
+            _ => Err(format!("Invalid layout: {}", s)),
+        }
+    }
+}
+
+/// Baseline settings for generated files.
+///
+/// Keys from the network itself always take precedence over these.
+#[derive(Default)]
+pub struct BaseSettings {
+    /// Settings for every network.
+    pub all: Option<Ini>,
+    /// Settings per security type, keyed by `Security::type_name`. These override `all`.
+    pub per_security: HashMap<String, Ini>,
+}
+
+impl BaseSettings {
+    /// Merge the applicable baselines into a configuration.
+    fn apply(&self, security: &Security, config: &mut Ini) {
+        let baselines = self.all.iter()
+            .chain(self.per_security.get(security.type_name()));
+
+        for baseline in baselines {
+            for (section, properties) in baseline.iter() {
+                for (key, value) in properties.iter() {
+                    config.set_to(section.clone(), key.clone(), value.clone());
+                }
+            }
+        }
     }
 }
 
@@ -83,152 +526,2289 @@ impl From<ParseError> for ConversionError {
 ///
 /// # Return value
 ///
-/// If the entry is valid and is a file, then return the full path. Otherwise return None.
+/// If the entry is valid and is a file, then return the full path. Otherwise return None, after
+/// printing a warning if the entry could not be read.
 fn map_dirent(base: &Path, entry: io::Result<DirEntry>) -> Option<String> {
-    if entry.is_err() {
-        return None;
-    }
-    let entry = entry.unwrap();
-    if !entry.file_type().unwrap().is_file() {
-        return None;
+    let entry = match entry {
+        Ok(entry) => entry,
+        Err(e) => {
+            eprintln!("Warning: failed to read an entry of {}: {}", base.display(), e);
+            return None;
+        }
+    };
+
+    match entry.file_type() {
+        Ok(file_type) if file_type.is_file() => {}
+        // Follow links to regular files, like netctl itself does. Link loops fail to resolve with
+        // ELOOP, so they are skipped with the warning below.
+        Ok(file_type) if file_type.is_symlink() => match fs::metadata(entry.path()) {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return None,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", entry.path().display(), e);
+                return None;
+            }
+        },
+        Ok(_) => return None,
+        Err(e) => {
+            eprintln!("Warning: skipping {}: {}", entry.path().display(), e);
+            return None;
+        }
     }
 
     let mut buf = PathBuf::from(base);
     buf.push(entry.file_name());
 
-    buf.to_str().map_or(None, |s| Some(s.to_owned()))
+    buf.to_str().map(|s| s.to_owned())
 }
 
-/// Convert a directory of profiles.
-pub fn convert_dir(input_dir: &str, output_dir: &str) {
-    let base_path = Path::new(input_dir);
-    let reader = read_dir(base_path);
-    match reader {
-        Ok(reader) => {
-            let iter = reader.filter_map(|f| map_dirent(base_path, f));
-            convert_files(iter, output_dir);
+/// Number of profiles per outcome of a conversion run.
+#[derive(Default, Debug, Eq, PartialEq)]
+pub struct Summary {
+    pub converted: usize,
+    pub failed: usize,
+    /// Profiles that weren't converted because their files already exist.
+    pub skipped: usize,
+    /// Converted profiles that replaced existing files, with `--force`.
+    pub overwritten: usize,
+    /// Converted profiles whose files already existed with the same contents.
+    pub unchanged: usize,
+    /// Profiles in the input directory that weren't converted because of `Options::exclude`.
+    pub excluded: usize,
+    /// Profiles that weren't converted because of `Options::only_security`.
+    pub filtered: usize,
+    /// The outcome for each generated file or failed profile.
+    pub records: Vec<Record>,
+}
+
+impl Summary {
+    /// Total number of profiles processed.
+    pub fn total(&self) -> usize {
+        self.converted + self.failed + self.skipped
+    }
+}
+
+impl From<&[Conversion]> for Summary {
+    fn from(results: &[Conversion]) -> Self {
+        let mut summary = Summary::default();
+
+        for (source, result) in results {
+            match result {
+                Ok(converted) => {
+                    summary.converted += 1;
+                    match converted.outcome {
+                        Outcome::Unchanged => summary.unchanged += 1,
+                        Outcome::Overwritten => summary.overwritten += 1,
+                        Outcome::Written => {}
+                    }
+                    summary.records.extend(converted.records.iter().cloned());
+                }
+                // Not a failure, nor worth a record.
+                Err(ConversionError::OtherSecurity(_)) => summary.filtered += 1,
+                Err(error) => {
+                    if let ConversionError::FileExists(_) = error {
+                        summary.skipped += 1;
+                    } else {
+                        summary.failed += 1;
+                    }
+                    summary.records.push(Record::failed(source, error));
+                }
+            }
         }
 
-        Err(e) => {
-            eprintln!("Failed to open {} for reading: {}", input_dir, e);
-            exit(e.raw_os_error().unwrap_or(1))
+        summary
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} converted", self.converted)?;
+        match (self.overwritten, self.unchanged) {
+            (0, 0) => {}
+            (overwritten, 0) => write!(f, " ({} overwritten)", overwritten)?,
+            (0, unchanged) => write!(f, " ({} unchanged)", unchanged)?,
+            (overwritten, unchanged) => write!(f, " ({} overwritten, {} unchanged)", overwritten, unchanged)?,
+        }
+        write!(f, ", {} skipped (already exist), {} failed", self.skipped, self.failed)?;
+        if self.excluded > 0 {
+            write!(f, ", {} excluded", self.excluded)?;
         }
+        if self.filtered > 0 {
+            write!(f, ", {} of other security types", self.filtered)?;
+        }
+        Ok(())
     }
 }
 
-/// Convert an iterable of profiles.
-pub fn convert_files<S>(input: impl Iterator<Item=S>, output_dir: &str)
-    where S: AsRef<Path> + Display {
-    for file in input {
-        match convert(file.as_ref(), output_dir) {
-            Ok(_) => println!("Successfully converted {}", file),
-            Err(error) => println!("Failed to convert {}: {}", file, error),
+/// Write a line with the source and error, separated by a tab, for each profile that failed to
+/// convert. Profiles that were skipped because their files already exist are left out.
+pub fn write_failures(writer: &mut impl Write, results: &[Conversion]) -> io::Result<()> {
+    for (source, result) in results {
+        match result {
+            Ok(_) | Err(ConversionError::FileExists(_)) | Err(ConversionError::OtherSecurity(_)) => continue,
+            // Keep each failure on a line of its own.
+            Err(error) => writeln!(writer, "{}\t{}", source, error.to_string().replace('\n', " "))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Machine-readable outcome of converting a profile.
+///
+/// A converted profile has a record for each file it generated, a failed one a single record without a file.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Record {
+    pub source: String,
+    /// Path of the iwd file relative to the output directory.
+    pub file: Option<String>,
+    pub ssid: Option<String>,
+    /// The security type, as named by `Security::type_name`.
+    pub security: Option<&'static str>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl Record {
+    fn converted(file: &OutputFile) -> Self {
+        Record {
+            source: file.source.clone(),
+            file: Some(file.name.clone()),
+            ssid: Some(file.ssid.clone()),
+            security: Some(file.security),
+            success: true,
+            error: None,
+        }
+    }
+
+    /// A record for a profile that could not be converted.
+    pub fn failed(source: &str, error: &ConversionError) -> Self {
+        Record {
+            source: source.to_owned(),
+            file: None,
+            ssid: None,
+            security: None,
+            success: false,
+            error: Some(error.to_string()),
         }
     }
 }
 
-fn convert(input: &Path, output_dir: &str) -> Result<(), ConversionError> {
-    let mut input = File::open(input)?;
-    let network = parse_network(&mut input)?;
+/// Number of profiles per outcome of a `--count` run.
+#[derive(Default, Debug, Eq, PartialEq)]
+pub struct Counts {
+    pub convertible: usize,
+    /// Profiles that aren't wireless networks at all.
+    pub skipped: usize,
+    pub errors: usize,
+}
 
-    let mut output_path = PathBuf::from_str(output_dir)?;
-    output_path.push(network.iwd_file_name());
+/// Make sure the output directory exists before anything is written to it, creating it and its
+/// parents (with `DIR_MODE`) if `create` is set.
+pub fn ensure_output_dir(output_dir: &Path, create: bool) -> Result<(), ConversionError> {
+    if output_dir.is_dir() {
+        Ok(())
+    } else if create {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(DIR_MODE)
+            .create(output_dir)
+            .map_err(|e| output_error(e, output_dir))
+    } else {
+        Err(ConversionError::MissingOutputDir(output_dir.to_owned()))
+    }
+}
 
-    let mut config = Ini::new();
-    network.write_config(&mut config);
+/// Check that files can be created in the output directory, by creating and removing a temporary
+/// one, so a read-only directory fails once instead of for every profile.
+pub fn probe_output_dir(output_dir: &Path) -> Result<(), ConversionError> {
+    let (path, _) = create_temp(&output_dir.join(".netctl2iwd-probe")).map_err(|e| output_error(e, output_dir))?;
+    fs::remove_file(&path).map_err(|e| output_error(e, &path))
+}
 
-    let mut output = OpenOptions::new()
+/// Take the advisory lock on an output directory, waiting for other runs to release it.
+///
+/// The lock is held until the returned file is dropped. The lock file itself is left in place,
+/// since removing it would allow a waiting run and a new one to lock different files.
+pub fn lock_output(output_dir: &Path) -> io::Result<File> {
+    let path = output_dir.join(LOCK_FILE);
+    let lock = OpenOptions::new()
         .write(true)
-        .create_new(true)
-        .open(output_path.as_path())?;
-    output.set_permissions(Permissions::from_mode(0o600))?;
-    config.write_to(&mut output)?;
+        .create(true)
+        .truncate(false)
+        .mode(OUTPUT_MODE)
+        .open(&path)?;
 
-    Ok(())
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("Waiting for another run to release {}", path.display());
+            lock.lock()?;
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+
+    Ok(lock)
 }
 
-/// Get a string according to the netctl quoting rules.
+/// Expand a leading `~` or `~user`, and `$VAR` or `${VAR}` references, in a command line path.
 ///
-/// See man netctl.profile, "SPECIAL QUOTING RULES" for definition.
+/// Only meant for paths given by the user, since the shell doesn't expand them everywhere,
+/// e.g. in `--output-dir=~/iwd`.
+pub fn expand_path(path: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+
+    if let Some(tilde) = path.strip_prefix('~') {
+        let (user, tail) = tilde.split_at(tilde.find('/').unwrap_or(tilde.len()));
+        let home = if user.is_empty() {
+            env::var("HOME").map_err(|_| "HOME is not set".to_owned())?
+        } else {
+            lookup_field(Path::new("/etc/passwd"), user, 5).ok_or_else(|| format!("No such user: {}", user))?
+        };
+
+        expanded.push_str(&home);
+        rest = tail;
+    }
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let reference = &rest[start + 1..];
+
+        let (name, tail) = match reference.strip_prefix('{') {
+            Some(braced) => braced.split_once('}').ok_or_else(|| format!("Unterminated variable in {}", path))?,
+            None => reference.split_at(reference.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(reference.len())),
+        };
+
+        if name.is_empty() {
+            // A lone $ is literal, as in the shell.
+            expanded.push('$');
+        } else {
+            expanded.push_str(&env::var(name).map_err(|_| format!("Environment variable {} is not set", name))?);
+        }
+        rest = tail;
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expand an input file name containing shell-style wildcards into the matching files.
 ///
-/// # Arguments
+/// Names without wildcards are returned as they are, even if the file doesn't exist.
+pub fn expand_input(input: &str) -> Result<Vec<String>, ConversionError> {
+    if !input.contains(['*', '?', '[']) {
+        return Ok(vec![input.to_owned()]);
+    }
+
+    let paths = glob::glob(input).map_err(|e| ConversionError::ParseError(format!("Invalid pattern {}: {}", input, e), Some(Box::new(e))))?;
+    let matches: Vec<_> = paths.filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .map(|path| path.display().to_string())
+        .collect();
+
+    if matches.is_empty() {
+        Err(ConversionError::NoMatches(input.to_owned()))
+    } else {
+        Ok(matches)
+    }
+}
+
+/// Find the profile file for a netctl profile name, as used by `netctl start <name>`.
+pub fn resolve_profile(netctl_dir: &Path, name: &str) -> Result<PathBuf, ConversionError> {
+    let path = netctl_dir.join(name);
+
+    if name.is_empty() || name.starts_with('.') || name.contains('/') || !path.is_file() {
+        Err(ConversionError::ProfileNotFound(path))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Convert a directory of profiles.
+///
+/// Fails only if the directory itself cannot be read.
+pub fn convert_dir(input_dir: &str, output_dir: &str, options: &Options) -> io::Result<Vec<Conversion>> {
+    let mut profiles = list_dir(input_dir, options.recursive)?;
+    exclude_profiles(&mut profiles, &options.exclude);
+    Ok(convert_files(profiles.iter(), output_dir, options))
+}
+
+/// Convert the profiles in several directories, in the order of the directories.
+///
+/// Set `dedupe` in the options to only convert the first profile for each network.
 ///
-/// * `config` the ini file to read from.
-/// * `key` the key to read from the config
+/// Fails only if one of the directories itself cannot be read.
+pub fn convert_dirs(input_dirs: &[&str], output_dir: &str, options: &Options) -> io::Result<Vec<Conversion>> {
+    let mut profiles = Vec::new();
+    for input_dir in input_dirs {
+        let mut listed = list_dir(input_dir, options.recursive)?;
+        exclude_profiles(&mut listed, &options.exclude);
+        profiles.extend(listed);
+    }
+
+    Ok(convert_files(profiles.iter(), output_dir, options))
+}
+
+/// Remove the profiles whose file name matches any of the patterns.
 ///
 /// # Return value
 ///
+/// The number of profiles removed.
+pub fn exclude_profiles(profiles: &mut Vec<String>, patterns: &[Pattern]) -> usize {
+    let before = profiles.len();
+    profiles.retain(|profile| {
+        let name = Path::new(profile).file_name().and_then(|name| name.to_str()).unwrap_or(profile);
+        !patterns.iter().any(|pattern| pattern.matches(name))
+    });
+
+    before - profiles.len()
+}
+
+/// List the profiles in a directory, and with `recursive` those in its subdirectories.
 ///
-/// Returns a
-fn get_quoted_string<'a>(config: &'a HashMap<String, String>, key: &str) -> Result<(&'a str, bool), ConversionError> {
-    if let Some(contents) = config.get(key) {
-        let quoted = contents.chars().next() != Some('"');
-        if quoted {
-            Ok((contents.as_str(), true))
-        } else {
-            Ok((&contents[1..], false))
-        }
+/// Fails only if the directory itself cannot be read.
+pub fn list_dir(input_dir: &str, recursive: bool) -> io::Result<Vec<String>> {
+    let base_path = Path::new(input_dir);
+
+    if recursive {
+        let mut profiles = Vec::new();
+        walk_dir(base_path, &mut HashSet::new(), &mut profiles)?;
+        Ok(profiles)
     } else {
-        Err(ConversionError::MissingKeys)
+        let reader = read_dir(base_path)?;
+        Ok(reader.filter_map(|f| map_dirent(base_path, f)).collect())
     }
 }
 
-pub fn parse_network(input: &mut impl Read) -> Result<Network, ConversionError> {
-    let contents = Ini::read_from(input)?;
-    let contents = contents.general_section();
+/// Collect the profiles in a directory and all of its subdirectories.
+///
+/// Symbolic links to directories are followed, but each directory is only visited once, so
+/// symlink loops are harmless. Unreadable subdirectories are skipped with a warning.
+fn walk_dir(dir: &Path, visited: &mut HashSet<(u64, u64)>, profiles: &mut Vec<String>) -> io::Result<()> {
+    let metadata = fs::metadata(dir)?;
+    if !visited.insert((metadata.dev(), metadata.ino())) {
+        return Ok(());
+    }
+
+    for entry in read_dir(dir)? {
+        if let Ok(entry) = &entry {
+            let path = entry.path();
+            if fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
+                if let Err(e) = walk_dir(&path, visited, profiles) {
+                    eprintln!("Warning: skipping {}: {}", path.display(), e);
+                }
+                continue;
+            }
+        }
 
-    if contents.get("Connection").map_or("invalid", |s| s.as_str()) != "wireless" {
-        return Err(ConversionError::NotWireless);
+        profiles.extend(map_dirent(dir, entry));
     }
 
-    let security = match contents.get("Security").map_or("none", |s| s.as_str()) {
-        "none" => Security::Open,
-        "wpa" => {
-            let (key, quoted) = get_quoted_string(contents, "Key")?;
-            let passphrase = if quoted {
-                PSKSecurity::Password(key.to_owned())
+    Ok(())
+}
+
+/// A converted profile.
+#[derive(Debug)]
+pub struct Converted {
+    /// A record for each generated file.
+    pub records: Vec<Record>,
+    pub warnings: Vec<Warning>,
+    /// Diagnostics about the conversion, with `verbose`.
+    pub details: Vec<String>,
+    pub outcome: Outcome,
+    /// Existing files that were moved aside with `backup`, and the paths of their backups.
+    pub backups: Vec<(PathBuf, PathBuf)>,
+}
+
+/// The source of a profile, and the result of converting it.
+pub type Conversion = (String, Result<Converted, ConversionError>);
+
+/// Convert an iterable of profiles.
+///
+/// The profiles are converted in parallel. Nothing is printed, so the caller decides how to report
+/// the results.
+///
+/// # Return value
+///
+/// The source and outcome of each profile, in the same order as the input. Profiles that are the
+/// same file as an earlier one, even through a symlink, are only converted once.
+pub fn convert_files<S>(input: impl Iterator<Item=S>, output_dir: &str, options: &Options) -> Vec<Conversion>
+    where S: AsRef<Path> + Display + Sync {
+    // Generate everything up front, so collisions are found before anything is written.
+    let input = unique_files(input);
+    let generated = input.iter().map(|file| file.to_string()).zip(generate_all(&input, options)).collect();
+
+    write_generated(generated, output_dir, options)
+}
+
+/// Leave out the files that were already given, by another path or through a symlink.
+fn unique_files<S: AsRef<Path>>(input: impl Iterator<Item=S>) -> Vec<S> {
+    let mut seen = HashSet::new();
+
+    input.filter(|file| {
+        // Files that can't be resolved are kept, so reading them reports why.
+        let path = fs::canonicalize(file.as_ref()).unwrap_or_else(|_| file.as_ref().to_owned());
+        seen.insert(path)
+    }).collect()
+}
+
+/// Write the files generated for each source, after checking them for collisions.
+fn write_generated(mut generated: Vec<(String, Generated)>, output_dir: &str, options: &Options) -> Vec<Conversion> {
+    let streaming = output_dir == STREAM_OUTPUT;
+    let mut streamed = Vec::new();
+    prepare(&mut generated, options);
+
+    let mut overwrite = Overwrite::new(options);
+    let mut results: Vec<Conversion> = generated.into_iter().map(|(source, result)| {
+        let result = result.and_then(|(files, mut warnings, details)| {
+            if let Some(log) = &options.log {
+                log.entry(&source, format_args!("parsed into {} file{}", files.len(), if files.len() == 1 { "" } else { "s" }));
+            }
+            let records = files.iter().map(Record::converted).collect();
+            let (outcome, backups) = if streaming {
+                // Streamed files are collected so they can be written in a stable order.
+                streamed.extend(files);
+                (Outcome::Written, Vec::new())
             } else {
-                PSKSecurity::PSK(key.to_owned())
+                write_files(files, output_dir, options, &mut overwrite, &mut warnings)?
             };
-            Security::PSK(passphrase)
+            Ok(Converted { records, warnings, details, outcome, backups })
+        });
+        if let Some(log) = &options.log {
+            log.result(&source, &result);
         }
-        _ => return Err(ConversionError::Unsupported)
-    };
+        (source, result)
+    }).collect();
 
-    if let Some(ssid) = contents.get("ESSID") {
-        Ok(Network::new(ssid.to_owned(), security))
-    } else {
-        Err(ConversionError::MissingSSID)
+    if streaming {
+        let stdout = io::stdout();
+        if let Err(e) = write_stream(&mut stdout.lock(), streamed, options.sort) {
+            for (_, result) in results.iter_mut().filter(|(_, result)| result.is_ok()) {
+                *result = Err(ConversionError::StreamError(io::Error::new(e.kind(), e.to_string())));
+            }
+        }
     }
+
+    results
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Drop the profiles for other interfaces, which aren't part of the conversion at all, and warn
+/// about collisions between the others.
+fn prepare(generated: &mut Vec<(String, Generated)>, options: &Options) {
+    generated.retain(|(source, result)| match result {
+        Err(e @ ConversionError::OtherInterface(_)) => {
+            if let Some(log) = &options.log {
+                log.entry(source, format_args!("ignored: {}", e));
+            }
+            false
+        }
+        _ => true,
+    });
+    if options.dedupe {
+        drop_duplicates(generated, options);
+    }
 
-    #[test]
-    fn test_get_quoted_string() {
-        let sample = b"quoted=quoted_value\nnon_quoted=\\\"non_quoted_value\n";
-        let ini = Ini::read_from(&mut sample.as_ref()).unwrap();
-        let contents = ini.general_section();
+    let outputs = generated.iter().filter_map(|(_, result)| result.as_ref().ok()).flat_map(|(files, _, _)| files);
+    let collisions: Vec<_> = find_collisions(outputs).into_iter()
+        .map(|(name, first, second)| (name.to_owned(), first.to_owned(), second.to_owned()))
+        .collect();
+    for (name, first, second) in collisions {
+        let later = generated.iter_mut().find(|(source, _)| *source == second);
+        if let Some((_, Ok((_, warnings, _)))) = later {
+            warnings.push(Warning::Collision(name, first));
+        }
+    }
+}
+
+/// Whether a generated file is the configuration of a network, rather than a copied certificate.
+fn is_config(name: &str, security: &str, options: &Options) -> bool {
+    let name = options.output_suffix.as_deref().and_then(|suffix| name.strip_suffix(suffix)).unwrap_or(name);
+    name.ends_with(&format!(".{}", security))
+}
+
+/// Leave out the files an earlier profile already generated, and warn the later profile about
+/// each network it loses.
+fn drop_duplicates(generated: &mut [(String, Generated)], options: &Options) {
+    let mut firsts: HashMap<String, String> = HashMap::new();
 
-        let (value, quoted) = get_quoted_string(contents, "quoted").unwrap();
-        assert_eq!("quoted_value", value);
-        assert!(quoted);
+    for (source, result) in generated.iter_mut() {
+        let (files, warnings, _) = match result {
+            Ok(generated) => generated,
+            Err(_) => continue,
+        };
 
-        let (value, quoted) = get_quoted_string(contents, "non_quoted").unwrap();
-        assert_eq!("non_quoted_value", value);
-        assert!(!quoted);
+        let mut dropped = Vec::new();
+        files.retain(|file| match firsts.get(&file.name) {
+            Some(first) if first != source => {
+                // Copied certificates don't need a warning of their own.
+                if is_config(&file.name, file.security, options) && !dropped.iter().any(|(ssid, _)| *ssid == file.ssid) {
+                    dropped.push((file.ssid.clone(), first.clone()));
+                }
+                false
+            }
+            _ => {
+                firsts.insert(file.name.clone(), source.clone());
+                true
+            }
+        });
+
+        warnings.extend(dropped.into_iter().map(|(ssid, first)| Warning::Duplicate(ssid, first)));
     }
+}
 
-    #[test]
-    fn test_parse_network() {
-        let sample = b"Connection=wireless\nESSID=foo_network\nKey=foo_password\nSecurity=wpa";
-        let network = parse_network(&mut sample.as_ref()).unwrap();
+/// The warnings for a profile, or why it can't be converted.
+pub type Check = (String, Result<Vec<Warning>, ConversionError>);
+
+/// Check which problems converting profiles would run into, without writing anything.
+///
+/// # Return value
+///
+/// The source of each profile with its warnings, including collisions with other profiles, or the
+/// error converting it would fail with. Profiles that aren't wireless are left out.
+pub fn check_files<S>(input: impl Iterator<Item=S>, options: &Options) -> Vec<Check>
+    where S: AsRef<Path> + Display + Sync {
+    let input = unique_files(input);
+    let mut generated = input.iter().map(|file| file.to_string()).zip(generate_all(&input, options)).collect();
+    prepare(&mut generated, options);
+
+    generated.into_iter()
+        .filter(|(_, result)| !matches!(result, Err(ConversionError::NotWireless) | Err(ConversionError::Wired) | Err(ConversionError::EmptyProfile)
+            | Err(ConversionError::OtherSecurity(_))))
+        .map(|(source, result)| (source, result.map(|(_, warnings, _)| warnings)))
+        .collect()
+}
+
+/// Count how many profiles would convert, without writing anything.
+pub fn count_files<S: AsRef<Path> + Sync>(input: impl Iterator<Item=S>, options: &Options) -> Counts {
+    let input: Vec<S> = input.collect();
+    count(generate_all(&input, options).iter())
+}
+
+/// A parsed profile, or why it couldn't be parsed.
+pub type Listing = (String, Result<Profile, ConversionError>);
+
+/// Parse profiles without generating or writing anything.
+///
+/// # Return value
+///
+/// The source and contents of each profile for `options.interface`, in the same order as the input.
+pub fn list_files<S: AsRef<Path> + Display>(input: impl Iterator<Item=S>, options: &Options) -> Vec<Listing> {
+    input.map(|file| (file.to_string(), read_profile(file.as_ref(), options)))
+        .filter(|(_, result)| result.as_ref().map_or(true, |profile| {
+            for_interface(profile, options) && profile.networks.iter().any(|network| of_security(network, options))
+        }))
+        .collect()
+}
+
+fn count<'a>(results: impl Iterator<Item=&'a Generated>) -> Counts {
+    let mut counts = Counts::default();
+
+    for result in results {
+        match result {
+            Ok(_) => counts.convertible += 1,
+            Err(ConversionError::NotWireless) | Err(ConversionError::Wired) | Err(ConversionError::EmptyProfile)
+            | Err(ConversionError::OtherInterface(_)) | Err(ConversionError::OtherSecurity(_)) => counts.skipped += 1,
+            Err(_) => counts.errors += 1,
+        }
+    }
+
+    counts
+}
+
+/// Convert the profiles in a tar archive, which may be gzipped, without extracting it.
+///
+/// Fails only if the archive itself cannot be read.
+pub fn convert_tar(archive: &str, output_dir: &str, options: &Options) -> io::Result<Vec<Conversion>> {
+    let generated = generate_tar(Path::new(archive), options)?;
+    Ok(write_generated(generated, output_dir, options))
+}
+
+/// Count how many profiles in a tar archive would convert, without writing anything.
+pub fn count_tar(archive: &str, options: &Options) -> io::Result<Counts> {
+    let generated = generate_tar(Path::new(archive), options)?;
+    Ok(count(generated.iter().map(|(_, result)| result)))
+}
+
+/// Generate the output files for each profile in a tar archive.
+///
+/// Like an input directory, only regular files at the top of the archive are profiles, unless
+/// `options.recursive` is set. An archive of `/etc` has its profiles in `netctl/`, so that counts
+/// as the top too.
+///
+/// # Return value
+///
+/// The source of each profile, as `archive:member`, and its generated files.
+fn generate_tar(archive: &Path, options: &Options) -> io::Result<Vec<(String, Generated)>> {
+    let mut reader = BufReader::new(File::open(archive)?);
+    let gzipped = reader.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let reader: Box<dyn Read> = if gzipped { Box::new(GzDecoder::new(reader)) } else { Box::new(reader) };
+
+    let mut generated = Vec::new();
+    for entry in Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+        let path = path.strip_prefix(".").unwrap_or(&path).to_owned();
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let top = parent == Path::new("") || parent.file_name() == Some("netctl".as_ref());
+        if !top && !options.recursive {
+            continue;
+        }
 
-        let correct_network = Network::new("foo_network".to_string(),
-                                           Security::PSK(PSKSecurity::Password("foo_password".to_string())));
+        let source = format!("{}:{}", archive.display(), path.display());
+        let name = path.file_name().and_then(|name| name.to_str()).map(str::to_owned);
+        let result = parse_named(&mut entry, name.as_deref(), options)
+            .and_then(|profile| generate_profile(profile, &source, options));
+        generated.push((source, result));
+    }
+
+    Ok(generated)
+}
+
+/// Write generated files to a stream, in the given order.
+pub fn write_stream(writer: &mut impl Write, mut files: Vec<OutputFile>, sort: SortKey) -> io::Result<()> {
+    files.sort_by(|a, b| {
+        let key = match sort {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Ssid => a.ssid.cmp(&b.ssid),
+            SortKey::Security => a.security.cmp(b.security),
+            SortKey::Source => a.source.cmp(&b.source),
+        };
 
-        assert_eq!(correct_network, network);
+        key.then_with(|| a.name.cmp(&b.name))
+    });
+
+    for file in files {
+        stream::write_entry(writer, &file.name, OUTPUT_MODE, &file.contents)?;
     }
-}
\ No newline at end of file
+
+    Ok(())
+}
+
+/// The generated files, warnings and details for a profile, or why it couldn't be converted.
+type Generated = Result<(Vec<OutputFile>, Vec<Warning>, Vec<String>), ConversionError>;
+
+/// Generate the output files for many profiles in parallel, bounded by `options.jobs`.
+///
+/// # Return value
+///
+/// The result for each profile, in the same order as the input.
+fn generate_all<S>(input: &[S], options: &Options) -> Vec<Generated>
+    where S: AsRef<Path> + Sync {
+    let jobs = match options.jobs {
+        0 => thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        jobs => jobs,
+    };
+
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results = Mutex::new((0..input.len()).map(|_| None).collect::<Vec<_>>());
+    // On anything but a terminal, the updates would only be noise.
+    let progress = options.progress && io::stderr().is_terminal();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(input.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let file = match input.get(index) {
+                    Some(file) => file,
+                    None => break,
+                };
+
+                let result = generate(file.as_ref(), options);
+                let mut results = results.lock().unwrap();
+                results[index] = Some(result);
+                if progress {
+                    // Counted under the lock, so the updates are printed in order.
+                    let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                    eprint!("\rProcessed {}/{} profiles", done, input.len());
+                }
+            });
+        }
+    });
+
+    if progress && !input.is_empty() {
+        eprintln!();
+    }
+
+    results.into_inner().unwrap().into_iter().map(|result| result.expect("every profile is generated")).collect()
+}
+
+/// Generate all output files for a profile.
+fn generate(input: &Path, options: &Options) -> Generated {
+    let source = input.display().to_string();
+    generate_profile(read_profile(input, options)?, &source, options)
+}
+
+/// Read a profile from a file, or from stdin for `STDIN_INPUT`.
+fn read_profile(input: &Path, options: &Options) -> Result<Profile, ConversionError> {
+    if input == Path::new(STDIN_INPUT) {
+        parse_named(&mut io::stdin().lock(), None, options)
+    } else if input.is_dir() {
+        Err(ConversionError::IsDirectory(input.to_owned()))
+    } else {
+        let name = input.file_name().and_then(|name| name.to_str());
+        parse_named(&mut File::open(input)?, name, options)
+    }
+}
+
+/// Parse a profile read from `reader`, which is called `name` by netctl.
+fn parse_named(reader: &mut impl Read, name: Option<&str>, options: &Options) -> Result<Profile, ConversionError> {
+    let mut profile = parse_profile(reader, &options.parse)?;
+
+    // netctl profiles are named after their file.
+    if let Some(name) = name {
+        profile.networks = profile.networks.into_iter().map(|network| network.with_profile(name)).collect();
+    }
+
+    Ok(profile)
+}
+
+/// Whether a profile is for the interface to convert for, if any.
+fn for_interface(profile: &Profile, options: &Options) -> bool {
+    options.interface.is_none() || profile.interface == options.interface
+}
+
+/// Whether a network has the security type to convert, if any.
+fn of_security(network: &Network, options: &Options) -> bool {
+    options.only_security.as_deref().is_none_or(|security| network.security().type_name() == security)
+}
+
+/// Generate all output files for a parsed profile.
+fn generate_profile(profile: Profile, source: &str, options: &Options) -> Generated {
+    let source = source.to_owned();
+    if !for_interface(&profile, options) {
+        return Err(ConversionError::OtherInterface(profile.interface));
+    }
+
+    let mut details = Vec::new();
+    if options.verbose >= 1 {
+        // The ignored keys are already reported as a warning.
+        details.push(format!("Read keys: {}", profile.keys.join(", ")));
+        if let Some(interface) = &profile.interface {
+            details.push(format!("For interface {}, which iwd doesn't bind networks to", interface));
+        }
+    }
+
+    let networks: Vec<_> = profile.networks.iter().filter(|network| of_security(network, options)).collect();
+    if networks.is_empty() {
+        if let Some(network) = profile.networks.first() {
+            return Err(ConversionError::OtherSecurity(network.security().type_name()));
+        }
+    }
+
+    let mut warnings = profile.warnings;
+    let mut files = Vec::new();
+    for network in networks {
+        if options.verbose >= 2 {
+            details.push(format!("Parsed {:?}", network));
+        }
+
+        let remapped;
+        let network = match network.ssid().to_str().and_then(|ssid| options.remap.get(ssid)) {
+            Some(ssid) => {
+                remapped = remap_ssid(network, ssid)?;
+                if options.verbose >= 1 {
+                    details.push(format!("{} is renamed to {}", network.ssid(), ssid));
+                }
+                &remapped
+            }
+            None => network,
+        };
+
+        let downgrades = network.downgrades();
+        if !downgrades.is_empty() {
+            if !options.allow_downgrade {
+                return Err(ConversionError::Downgrade(network.ssid().to_string(), downgrades));
+            }
+            warnings.push(Warning::Downgrade(network.ssid().to_string(), downgrades));
+        }
+
+        let network = &match &options.cert_dir {
+            Some(cert_dir) => copy_certs(network, cert_dir, &source, &mut files)?,
+            None => network.clone(),
+        };
+
+        for (name, contents) in render_files(network, options)? {
+            if options.verbose >= 1 {
+                details.push(format!("{} is written to {}", network.ssid(), name));
+            }
+            let secret = Path::new(&name).parent().is_some_and(|dir| dir.ends_with(SECRETS_DIR));
+            files.push(OutputFile {
+                name,
+                contents,
+                ssid: network.ssid().to_string(),
+                security: network.security().type_name(),
+                source: source.clone(),
+                secret,
+            });
+        }
+    }
+
+    Ok((files, warnings, details))
+}
+
+/// Rename a network, whose PSK is then derived from the passphrase and the new SSID.
+fn remap_ssid(network: &Network, ssid: &str) -> Result<Network, ConversionError> {
+    if ssid.is_empty() || ssid.len() > MAX_SSID_LENGTH {
+        return Err(ConversionError::InvalidSSID(ssid.to_owned()));
+    }
+    if let Security::PSK(PSKSecurity::PSK(_)) = network.security() {
+        return Err(ConversionError::RemappedRawPSK(network.ssid().to_string(), ssid.to_owned()));
+    }
+
+    Ok(network.clone().with_ssid(ssid))
+}
+
+/// Add copies of the files referenced by an enterprise network to the generated files.
+///
+/// # Return value
+///
+/// The network, referring to the copies in `cert_dir` instead.
+fn copy_certs(network: &Network, cert_dir: &Path, source: &str, files: &mut Vec<OutputFile>) -> Result<Network, ConversionError> {
+    let mut security = match network.security() {
+        Security::EAP(security) => security.clone(),
+        _ => return Ok(network.clone()),
+    };
+
+    let key = security.client_key.clone();
+    for path in security.files_mut() {
+        let secret = key.as_deref() == Some(path.as_str());
+        let original = PathBuf::from(&*path);
+        let name = original.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| ConversionError::UnreadableCertificate(original.clone(), ErrorKind::InvalidInput.into()))?
+            .to_owned();
+        let contents = fs::read(&original).map_err(|e| ConversionError::UnreadableCertificate(original.clone(), e))?;
+
+        *path = cert_dir.join(&name).display().to_string();
+        files.push(OutputFile {
+            name,
+            contents,
+            ssid: network.ssid().to_string(),
+            security: network.security().type_name(),
+            source: source.to_owned(),
+            secret,
+        });
+    }
+
+    Ok(network.clone().with_security(Security::EAP(security)))
+}
+
+/// Find the generated files with the same name but different contents, from different profiles.
+///
+/// # Return value
+///
+/// The name of each colliding file, with the profile that generated it first and the one that generated it again.
+fn find_collisions<'a>(files: impl Iterator<Item=&'a OutputFile>) -> Vec<(&'a str, &'a str, &'a str)> {
+    let mut sources = HashMap::new();
+    let mut collisions = Vec::new();
+
+    for file in files {
+        let (first, contents) = *sources.entry(file.name.as_str()).or_insert((file.source.as_str(), &file.contents));
+        // Identical files, like a shared certificate, can't get in each other's way.
+        if first != file.source && *contents != file.contents {
+            collisions.push((file.name.as_str(), first, file.source.as_str()));
+        }
+    }
+
+    collisions
+}
+
+/// What writing a profile did to the output directory, from least to most invasive.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Outcome {
+    /// The existing files already had the generated contents.
+    Unchanged,
+    Written,
+    /// An existing file was replaced, with `force`.
+    Overwritten,
+}
+
+/// Write the generated files of a profile into the output directory.
+///
+/// # Return value
+///
+/// The most invasive outcome of writing the files, and the backups that were made.
+fn write_files(files: Vec<OutputFile>, output_dir: &str, options: &Options, overwrite: &mut Overwrite, warnings: &mut Vec<Warning>) -> Result<(Outcome, Vec<(PathBuf, PathBuf)>), ConversionError> {
+    let output_dir = PathBuf::from_str(output_dir)?;
+    let mut outcome = Outcome::Unchanged;
+    let mut backups = Vec::new();
+
+    for OutputFile { name, contents, source, security, secret, .. } in files {
+        if options.stdout {
+            // iwd ignores comments, so the output can still be split into valid files.
+            let mut stdout = io::stdout().lock();
+            writeln!(stdout, "# {} from {}", name, source)?;
+            stdout.write_all(&contents)?;
+            outcome = outcome.max(Outcome::Written);
+        } else if options.dry_run {
+            println!("Would write {}:", output_dir.join(name).display());
+            io::stdout().write_all(&contents)?;
+            outcome = outcome.max(Outcome::Written);
+        } else {
+            // Only configurations can be merged, not copied certificates.
+            let merging = options.merge && is_config(&name, security, options) && output_dir.join(&name).exists();
+            let (contents, overwrite) = if merging {
+                let (merged, replaced) = merge_config(&output_dir.join(&name), &contents)?;
+                if !replaced.is_empty() {
+                    warnings.push(Warning::MergedKeys(name.clone(), replaced));
+                }
+                (merged, &mut Overwrite::Always)
+            } else {
+                (contents, &mut *overwrite)
+            };
+
+            let (path, written, backup) = write_file(&output_dir, &name, &contents, secret, options, overwrite)?;
+            if let Some(log) = &options.log {
+                let action = match written {
+                    Outcome::Unchanged => "unchanged",
+                    Outcome::Written => "wrote",
+                    Outcome::Overwritten => "replaced",
+                };
+                log.entry(&source, format_args!("{} {}", action, path.display()));
+            }
+            if options.preserve_times && written != Outcome::Unchanged {
+                preserve_times(&path, &source);
+            }
+            outcome = outcome.max(written);
+            backups.extend(backup.map(|backup| (path, backup)));
+        }
+    }
+
+    Ok((outcome, backups))
+}
+
+/// Overlay a generated configuration on the existing one at `path`, keeping the sections and keys
+/// that were only added there.
+///
+/// The `[Security]` section is replaced as a whole, since keys like a stale `PreSharedKey` could
+/// contradict the new passphrase.
+///
+/// # Return value
+///
+/// The merged configuration, and the existing keys with other values that were replaced, like
+/// `IPv4.Address`, in alphabetical order.
+fn merge_config(path: &Path, contents: &[u8]) -> Result<(Vec<u8>, Vec<String>), ConversionError> {
+    let mut merged = Ini::load_from_str(&fs::read_to_string(path)?)?;
+    let generated = Ini::load_from_str(&String::from_utf8_lossy(contents))?;
+    let mut replaced = Vec::new();
+
+    if let Some(security) = generated.section(Some("Security")) {
+        let existing = merged.delete(Some("Security")).unwrap_or_default();
+        replaced.extend(existing.iter()
+            .filter(|(key, value)| security.get(*key) != Some(*value))
+            .map(|(key, _)| format!("Security.{}", key)));
+    }
+
+    for (section, properties) in generated.iter() {
+        for (key, value) in properties.iter() {
+            let section = section.as_deref();
+            if merged.get_from(section, key).is_some_and(|existing| existing != value) {
+                replaced.push(format!("{}.{}", section.unwrap_or_default(), key));
+            }
+            merged.set_to(section, key.to_owned(), value.to_owned());
+        }
+    }
+
+    let mut text = Vec::new();
+    write_sorted(&merged, &mut text)?;
+    replaced.sort();
+    Ok((text, replaced))
+}
+
+/// Give a written file the access and modification times of the profile it was generated from.
+///
+/// Sources without times of their own, like stdin or tar members, leave the file alone.
+fn preserve_times(path: &Path, source: &str) {
+    let times = match fs::metadata(source).and_then(|m| Ok(FileTimes::new().set_accessed(m.accessed()?).set_modified(m.modified()?))) {
+        Ok(times) => times,
+        Err(_) => return,
+    };
+
+    if let Err(e) = File::open(path).and_then(|file| file.set_times(times)) {
+        eprintln!("Warning: failed to set the times of {}: {}", path.display(), e);
+    }
+}
+
+/// Whether existing files with different contents are replaced.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Overwrite {
+    Never,
+    /// Ask on the terminal for each file.
+    Ask,
+    Always,
+}
+
+impl Overwrite {
+    fn new(options: &Options) -> Self {
+        if options.force {
+            Overwrite::Always
+        } else if options.interactive && io::stdin().is_terminal() {
+            Overwrite::Ask
+        } else {
+            // Without a terminal to ask on, keep the safe default instead of blocking.
+            Overwrite::Never
+        }
+    }
+
+    /// Decide whether to replace an existing file, asking if needed.
+    ///
+    /// Answering "all" replaces this and every later file without asking again.
+    fn allows(&mut self, path: &Path) -> bool {
+        match self {
+            Overwrite::Never => false,
+            Overwrite::Always => true,
+            Overwrite::Ask => {
+                // The prompt goes to stderr, to keep stdout clean.
+                eprint!("Overwrite {}? [y/N/a] ", path.display());
+
+                let mut answer = String::new();
+                if io::stdin().read_line(&mut answer).is_err() {
+                    return false;
+                }
+
+                match answer.trim() {
+                    "y" | "Y" | "yes" => true,
+                    "a" | "A" | "all" => {
+                        *self = Overwrite::Always;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Write a single generated file, creating its subdirectory if needed.
+///
+/// Existing files with the same contents are left alone. Others are only replaced if `overwrite`
+/// allows it, after moving them aside with `backup`.
+///
+/// # Return value
+///
+/// The path of the file, what was done to it, and the path of the backup if one was made.
+fn write_file(output_dir: &Path, name: &str, contents: &[u8], secret: bool, options: &Options, overwrite: &mut Overwrite) -> Result<(PathBuf, Outcome, Option<PathBuf>), ConversionError> {
+    let output_path = output_dir.join(name);
+    // File names are derived from SSIDs, so make sure no SSID can ever escape the output directory.
+    if !Path::new(name).components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(ConversionError::OutsideOutputDir(output_path));
+    }
+
+    if let Some(parent) = output_path.parent().filter(|p| *p != output_dir) {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(DIR_MODE)
+            .create(parent)
+            .map_err(|e| output_error(e, parent))?;
+    }
+
+    // Subdirectories may also be symbolic links pointing elsewhere.
+    let parent = output_path.parent().unwrap_or(output_dir);
+    if !parent.canonicalize()?.starts_with(output_dir.canonicalize()?) {
+        return Err(ConversionError::OutsideOutputDir(output_path));
+    }
+
+    // Re-running a conversion is a no-op, rather than a failure, even on another day.
+    if fs::read(&output_path).is_ok_and(|existing| without_stamp(&existing) == without_stamp(contents)) {
+        return Ok((output_path, Outcome::Unchanged, None));
+    }
+
+    let existed = output_path.exists() && overwrite.allows(&output_path);
+    let backup = if existed && options.backup {
+        Some(back_up(&output_path).map_err(|e| output_error(e, &output_path))?)
+    } else {
+        None
+    };
+
+    // Write to a temporary file first, so an interrupted run never leaves a truncated file behind.
+    let (temp_path, mut output) = create_temp(&output_path).map_err(|e| output_error(e, &output_path))?;
+    // Secrets are never made readable to others, whatever `Options::mode` says.
+    let mode = if secret { OUTPUT_MODE } else { options.mode.unwrap_or(OUTPUT_MODE) };
+    let result = fill_temp(&mut output, &output_path, contents, mode, options).and_then(|()| if existed {
+        // Atomically replaces the existing file.
+        fs::rename(&temp_path, &output_path).map_err(|e| output_error(e, &output_path))
+    } else {
+        // Unlike a rename, linking never replaces a file that was created in the meantime.
+        fs::hard_link(&temp_path, &output_path).map_err(|e| output_error(e, &output_path))
+    });
+    // After a rename, there is nothing left to remove.
+    let _ = fs::remove_file(&temp_path);
+    result?;
+
+    let outcome = if existed { Outcome::Overwritten } else { Outcome::Written };
+    Ok((output_path, outcome, backup))
+}
+
+/// Create a new temporary file next to `path`, which iwd ignores because of its extension.
+///
+/// # Return value
+///
+/// The path of the temporary file, and the file opened for writing.
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut attempt = 0;
+    loop {
+        let temp_path = path.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), attempt));
+        match OpenOptions::new().write(true).create_new(true).mode(OUTPUT_MODE).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Give a temporary file for `output_path` its permissions, owner and contents.
+fn fill_temp(output: &mut File, output_path: &Path, contents: &[u8], mode: u32, options: &Options) -> Result<(), ConversionError> {
+    output.set_permissions(Permissions::from_mode(mode))?;
+    if let Some(Owner { uid, gid }) = options.owner {
+        unix::fs::fchown(&*output, uid, gid).map_err(|e| match e.kind() {
+            ErrorKind::PermissionDenied => ConversionError::OwnershipDenied(output_path.to_owned()),
+            _ => ConversionError::from(e),
+        })?;
+    }
+    output.write_all(contents)?;
+    // Make sure the contents are on disk before the file takes the place of the old one.
+    output.sync_all()?;
+
+    Ok(())
+}
+
+/// Link an existing file to `<name>.bak`, or `<name>.bak.<n>` if that backup already exists, so
+/// replacing the file leaves the original as the backup.
+///
+/// The backup is the original file, so its permissions are preserved.
+///
+/// # Return value
+///
+/// The path of the backup.
+fn back_up(path: &Path) -> io::Result<PathBuf> {
+    let mut attempt = 0;
+    loop {
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(".bak");
+        if attempt > 0 {
+            backup.push(format!(".{}", attempt));
+        }
+
+        // Unlike a rename, linking never replaces an existing backup.
+        let backup = PathBuf::from(backup);
+        match fs::hard_link(path, &backup) {
+            Ok(()) => return Ok(backup),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Parse a netctl profile into the network it describes.
+///
+/// This is `parse_network`, under the name used by the library interface.
+pub fn convert_reader(input: &mut impl Read) -> Result<Network, ConversionError> {
+    parse_network(input)
+}
+
+/// Write the iwd file for a network to a directory, with the default options.
+///
+/// # Return value
+///
+/// The path of the written file, whose name is derived from the SSID.
+pub fn write_network(network: &Network, output_dir: &Path) -> Result<PathBuf, ConversionError> {
+    let mut paths = render_files(network, &Options::default())?.into_iter()
+        .map(|(name, contents)| write_file(output_dir, &name, &contents, false, &Options::default(), &mut Overwrite::Never).map(|(path, _, _)| path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The classic layout produces a single file.
+    Ok(paths.remove(0))
+}
+
+/// Build the iwd configuration for a network.
+fn build_config(network: &Network, options: &Options) -> Ini {
+    let mut config = Ini::new();
+    options.base_settings.apply(network.security(), &mut config);
+    network.write_config(&mut config, &options.write);
+
+    config
+}
+
+/// Start of the comment that `Options::stamp` adds to each file.
+const STAMP: &str = "# Generated by netctl2iwd";
+
+/// Leave out the stamp line of a file's contents, if it has one, since its date differs between
+/// runs.
+fn without_stamp(contents: &[u8]) -> &[u8] {
+    if !contents.starts_with(STAMP.as_bytes()) {
+        return contents;
+    }
+
+    match contents.iter().position(|&c| c == b'\n') {
+        Some(end) => &contents[end + 1..],
+        None => &[],
+    }
+}
+
+/// Serialize an iwd configuration, adding a stamp if requested.
+fn serialize(config: &Ini, network: &Network, options: &Options) -> Result<Vec<u8>, ConversionError> {
+    let mut contents = Vec::new();
+
+    if options.stamp {
+        // iwd ignores lines starting with #, so this doesn't affect the configuration.
+        writeln!(contents, "{} {} on {}", STAMP, env!("CARGO_PKG_VERSION"), today())?;
+
+        // iwd has no field for a display name, so it's kept for humans only.
+        if let Some(name) = network.display_name() {
+            writeln!(contents, "# Network: {}", name.replace('\n', " "))?;
+        }
+    }
+
+    if options.annotate_open && *network.security() == Security::Open {
+        writeln!(contents, "# Open network: iwd needs no settings to connect, so this file may be empty.")?;
+    }
+
+    write_sorted(config, &mut contents)?;
+
+    Ok(contents)
+}
+
+/// Sections of iwd configurations in the order they are written, before any others.
+const SECTION_ORDER: &[&str] = &["[Security]", "[Settings]", "[IPv4]", "[IPv6]"];
+
+/// Write an iwd configuration with its sections and keys in a stable order.
+///
+/// `Ini` keeps both in hash maps, so its own order changes from run to run, and unchanged
+/// configurations wouldn't be recognized as such. The sections come in the order of
+/// `SECTION_ORDER`, and the keys alphabetically; every line ends with a newline.
+fn write_sorted(config: &Ini, writer: &mut impl Write) -> io::Result<()> {
+    let mut text = Vec::new();
+    config.write_to(&mut text)?;
+    let text = String::from_utf8(text).map_err(io::Error::other)?;
+
+    // Values are escaped, so every line is a section header or a single key.
+    let mut general = Vec::new();
+    let mut sections: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        if line.starts_with('[') {
+            sections.push((line, Vec::new()));
+        } else if let Some((_, keys)) = sections.last_mut() {
+            keys.push(line);
+        } else {
+            general.push(line);
+        }
+    }
+
+    general.sort_unstable();
+    // Unknown sections, like those from base settings, go last.
+    sections.sort_unstable_by_key(|(header, _)| {
+        (SECTION_ORDER.iter().position(|known| known == header).unwrap_or(SECTION_ORDER.len()), *header)
+    });
+
+    let mut first = true;
+    for line in &general {
+        writeln!(writer, "{}", line)?;
+        first = false;
+    }
+
+    for (header, mut keys) in sections {
+        if !first {
+            writeln!(writer)?;
+        }
+        first = false;
+
+        keys.sort_unstable();
+        writeln!(writer, "{}", header)?;
+        for key in keys {
+            writeln!(writer, "{}", key)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Serialize the iwd configuration for a network.
+pub fn render_config(network: &Network, options: &Options) -> Result<Vec<u8>, ConversionError> {
+    serialize(&build_config(network, options), network, options)
+}
+
+/// Serialize all files for a network, according to the configured layout.
+///
+/// # Return value
+///
+/// Pairs of the path relative to the output directory and the file contents.
+pub fn render_files(network: &Network, options: &Options) -> Result<Vec<(String, Vec<u8>)>, ConversionError> {
+    let name = match &options.output_name {
+        Some(name) if name.ends_with(&network.security().get_extension()) => name.clone(),
+        Some(name) => format!("{}{}", name, network.security().get_extension()),
+        None => network.iwd_file_name(options.write.hex_case),
+    };
+    let name = name + options.output_suffix.as_deref().unwrap_or("");
+    let mut config = build_config(network, options);
+    let mut files = Vec::new();
+
+    if options.layout == Layout::Split {
+        if let Some(security) = config.delete(Some("Security")) {
+            let mut secrets = Ini::new();
+            for (key, value) in security {
+                secrets.set_to(Some("Security"), key, value);
+            }
+            files.push((format!("{}/{}", SECRETS_DIR, name), serialize(&secrets, network, options)?));
+        }
+    }
+
+    let mut contents = serialize(&config, network, options)?;
+    if options.write.passthrough {
+        // iwd ignores comments, so whatever couldn't be translated is kept for reference.
+        for (key, value, reason) in network.untranslated_options() {
+            writeln!(contents, "# wpa_supplicant {}={} ({})", key, value.replace('\n', " "), reason)?;
+        }
+    }
+    files.insert(0, (name, contents));
+
+    if options.split_by_security {
+        let dir = network.security().type_name();
+        files = files.into_iter().map(|(name, contents)| (format!("{}/{}", dir, name), contents)).collect();
+    }
+
+    Ok(files)
+}
+
+/// Format the current (UTC) date as YYYY-MM-DD.
+fn today() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The current time in UTC, like `2021-03-14T15:09:26Z`.
+fn now() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+/// Convert a number of days since the unix epoch to a (year, month, day) triple.
+///
+/// This is Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ip::IpConfig;
+    use crate::ip::StaticConfig;
+    use crate::networks::compute_psk;
+    use crate::networks::HexCase;
+
+    use super::*;
+
+    #[test]
+    fn test_io_error_mapping() {
+        // EROFS, as returned by the kernel when writing to a read-only mount.
+        let error = io::Error::from_raw_os_error(30);
+        let error = ConversionError::from(error);
+        assert!(matches!(error, ConversionError::ReadOnlyFilesystem(_)));
+        assert_eq!(Some(ErrorKind::ReadOnlyFilesystem), error.source()
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .map(io::Error::kind));
+
+        let error = io::Error::from(ErrorKind::PermissionDenied);
+        assert!(matches!(ConversionError::from(error), ConversionError::PermissionDenied(_)));
+
+        let error = io::Error::from(ErrorKind::AlreadyExists);
+        assert!(matches!(ConversionError::from(error), ConversionError::FileExists(_)));
+
+        let path = Path::new("/var/lib/iwd/foo_network.psk");
+        let error = output_error(io::Error::from(ErrorKind::PermissionDenied), path);
+        assert!(error.to_string().contains("/var/lib/iwd/foo_network.psk"));
+        assert!(error.to_string().contains("--output-dir"));
+    }
+
+    #[test]
+    fn test_error_source() {
+        let error = ConversionError::from(io::Error::other("disk on fire"));
+        assert_eq!("Unknown error", error.to_string());
+        assert_eq!("disk on fire", error.source().unwrap().to_string());
+
+        let error = Ini::load_from_str("[unterminated").map(|_| ()).map_err(ConversionError::from).unwrap_err();
+        assert!(error.source().is_some());
+        assert!(ConversionError::MissingSSID.source().is_none());
+    }
+
+    #[test]
+    fn test_stamp() {
+        let network = Network::new("foo_network".to_string(),
+                                   Security::PSK(PSKSecurity::Password("foo_password".to_string())));
+        let options = Options { stamp: true, ..Options::default() };
+
+        let contents = render_config(&network, &options).unwrap();
+        let text = String::from_utf8(contents).unwrap();
+        assert!(text.starts_with(&format!("# Generated by netctl2iwd {} on ", env!("CARGO_PKG_VERSION"))));
+
+        let parsed = Ini::load_from_str(&text).unwrap();
+        assert_eq!(parsed.get_from(Some("Security"), "Passphrase"), Some("foo_password"));
+    }
+
+    #[test]
+    fn test_directory_input() {
+        let input = tempfile::tempdir().unwrap();
+        assert!(matches!(generate(input.path(), &Options::default()), Err(ConversionError::IsDirectory(path)) if path == input.path()));
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("sae");
+        std::fs::write(&profile, b"Connection=wireless\nSecurity=wpa-configsection\n\
+            WPAConfigSection=('ssid=\"foo_network\"' 'key_mgmt=SAE' 'sae_password=\"foo_password\"' \
+            'proto=RSN' 'disabled=1' 'id_str=\"home\"')\n").unwrap();
+
+        let mut options = Options { allow_downgrade: true, ..Options::default() };
+        options.write.passthrough = true;
+        let (files, _, _) = generate(&profile, &options).unwrap();
+        let text = String::from_utf8(files[0].contents.clone()).unwrap();
+        assert!(text.contains("AutoConnect=false\n"));
+        assert!(text.contains("# wpa_supplicant id_str=home (no iwd equivalent)\n"));
+        assert!(text.contains("# wpa_supplicant proto=RSN (negotiated by iwd)\n"));
+        assert!(!text.contains("disabled"));
+
+        let (files, _, _) = generate(&profile, &Options { allow_downgrade: true, ..Options::default() }).unwrap();
+        assert!(!String::from_utf8(files[0].contents.clone()).unwrap().contains("AutoConnect"));
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("sae");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=foo_network\nSecurity=sae\nKey=\"foo_password\"\n").unwrap();
+
+        assert!(matches!(generate(&profile, &Options::default()), Err(ConversionError::Downgrade(ssid, _)) if ssid == "foo_network"));
+
+        let options = Options { allow_downgrade: true, ..Options::default() };
+        let (files, warnings, _) = generate(&profile, &options).unwrap();
+        assert_eq!("foo_network.psk", files[0].name);
+        assert!(matches!(&warnings[..], [Warning::Downgrade(..)]));
+    }
+
+    #[test]
+    fn test_output_name() {
+        let network = Network::new("foo_network".to_owned(), Security::Open);
+        let name = |output_name: &str| {
+            let options = Options { output_name: Some(output_name.to_owned()), ..Default::default() };
+            render_files(&network, &options).unwrap().remove(0).0
+        };
+
+        assert_eq!("Home.open", name("Home"));
+        assert_eq!("Home.open", name("Home.open"));
+        assert_eq!("Home.psk.open", name("Home.psk"));
+    }
+
+    #[test]
+    fn test_split_by_security() {
+        let output = tempfile::tempdir().unwrap();
+        let network = Network::new("foo_network".to_owned(), Security::PSK(PSKSecurity::Password("foo_password".to_owned())));
+        let options = Options { split_by_security: true, layout: Layout::Split, ..Default::default() };
+
+        let names: Vec<_> = render_files(&network, &options).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(vec!["psk/foo_network.psk", "psk/secrets/foo_network.psk"], names);
+
+        let input = output.path().join("open");
+        std::fs::write(&input, b"Connection=wireless\nESSID=foo_network\nSecurity=none\n").unwrap();
+        let output_dir = output.path().join("iwd");
+        convert_files([input.display().to_string()].iter(), output_dir.to_str().unwrap(), &options);
+        let open_dir = output_dir.join("open");
+        assert!(open_dir.join("foo_network.open").exists());
+        assert_eq!(0o700, std::fs::metadata(&open_dir).unwrap().mode() & 0o777);
+    }
+
+    #[test]
+    fn test_display_name() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("home");
+        std::fs::write(&profile, b"Description='My home network'\nConnection=wireless\nESSID=foo_network\n").unwrap();
+
+        let options = Options { stamp: true, ..Options::default() };
+        let (files, _, _) = generate(&profile, &options).unwrap();
+        let text = String::from_utf8(files[0].contents.clone()).unwrap();
+        assert_eq!(Some("# Network: My home network (profile home)"), text.lines().nth(1));
+
+        // Without a stamp, the files contain only the configuration.
+        let (files, _, _) = generate(&profile, &Options::default()).unwrap();
+        assert!(!String::from_utf8(files[0].contents.clone()).unwrap().contains('#'));
+    }
+
+    #[test]
+    fn test_annotate_open() {
+        let network = Network::new("open_network".to_owned(), Security::Open);
+        assert!(render_config(&network, &Options::default()).unwrap().is_empty());
+
+        let options = Options { annotate_open: true, ..Options::default() };
+        let text = String::from_utf8(render_config(&network, &options).unwrap()).unwrap();
+        assert!(!text.is_empty());
+        assert!(text.starts_with("# Open network"));
+
+        let parsed = Ini::load_from_str(&text).unwrap();
+        assert!(parsed.section(Some("Security")).is_none());
+        assert_eq!("open_network.open", network.iwd_file_name(HexCase::Lower));
+
+        // Secured networks have settings, so they aren't annotated.
+        let network = Network::new("foo_network".to_owned(), Security::PSK(PSKSecurity::Password("foo_password".to_owned())));
+        assert!(!String::from_utf8(render_config(&network, &options).unwrap()).unwrap().contains('#'));
+    }
+
+    #[test]
+    fn test_hidden() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nHidden=yes\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert!(network.hidden());
+
+        let text = String::from_utf8(render_config(&network, &Options::default()).unwrap()).unwrap();
+        let parsed = Ini::load_from_str(&text).unwrap();
+        assert_eq!(parsed.get_from(Some("Settings"), "Hidden"), Some("true"));
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nHidden=no\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert!(render_config(&network, &Options::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_library_api() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n";
+        let network = convert_reader(&mut sample.as_ref()).unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let path = write_network(&network, output.path()).unwrap();
+        assert_eq!(output.path().join("foo_network.psk"), path);
+
+        let config = Ini::load_from_file(&path).unwrap();
+        assert_eq!(config.get_from(Some("Security"), "Passphrase"), Some("foo_password"));
+        assert_eq!(path, write_network(&network, output.path()).unwrap());
+
+        std::fs::write(&path, b"[Security]\nPassphrase=other_password\n").unwrap();
+        assert!(matches!(write_network(&network, output.path()), Err(ConversionError::FileExists(_))));
+    }
+
+    #[test]
+    fn test_force() {
+        let output = tempfile::tempdir().unwrap();
+        std::fs::write(output.path().join("foo.psk"), b"old contents").unwrap();
+
+        assert!(matches!(write_file(output.path(), "foo.psk", b"new", false, &Options::default(), &mut Overwrite::Never), Err(ConversionError::FileExists(_))));
+
+        let force = Options { force: true, ..Options::default() };
+        let (path, outcome, _) = write_file(output.path(), "foo.psk", b"new", false, &force, &mut Overwrite::Always).unwrap();
+        assert_eq!(Outcome::Overwritten, outcome);
+        assert_eq!(b"new".as_ref(), std::fs::read(&path).unwrap().as_slice());
+        assert_eq!(OUTPUT_MODE, std::fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+
+        let (_, outcome, _) = write_file(output.path(), "bar.psk", b"new", false, &force, &mut Overwrite::Always).unwrap();
+        assert_eq!(Outcome::Written, outcome);
+    }
+
+    #[test]
+    fn test_preserve_times() {
+        let input = tempfile::tempdir().unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let profile = input.path().join("open");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=foo_network\nSecurity=none\n").unwrap();
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        File::options().write(true).open(&profile).unwrap().set_times(FileTimes::new().set_modified(modified)).unwrap();
+
+        let options = Options { preserve_times: true, ..Options::default() };
+        let results = convert_files([profile.display().to_string()].iter(), output.path().to_str().unwrap(), &options);
+        assert!(results[0].1.is_ok());
+        assert_eq!(modified, std::fs::metadata(output.path().join("foo_network.open")).unwrap().modified().unwrap());
+    }
+
+    #[test]
+    fn test_write_sorted() {
+        let mut config = Ini::new();
+        config.with_section(Some("Settings")).set("Hidden", "true");
+        config.with_section(Some("Security")).set("Passphrase", "foo_password").set("PreSharedKey", "0123");
+        config.with_section(None::<String>).set("Name", "value");
+
+        let mut contents = Vec::new();
+        write_sorted(&config, &mut contents).unwrap();
+        let expected = "Name=value\n\n[Security]\nPassphrase=foo_password\nPreSharedKey=0123\n\n[Settings]\nHidden=true\n";
+        assert_eq!(expected, String::from_utf8(contents).unwrap());
+    }
+
+    #[test]
+    fn test_render_config_exact() {
+        let ipv4 = IpConfig::Static(StaticConfig {
+            addresses: vec!["192.168.1.10/24".parse().unwrap()],
+            gateway: Some([192, 168, 1, 1].into()),
+            dns: Vec::new(),
+            routes: Vec::new(),
+            domain_name: None,
+        });
+        let network = Network::builder()
+            .ssid("foo_network")
+            .security(Security::PSK(PSKSecurity::Password("foo_password".to_owned())))
+            .hidden(true)
+            .ipv4(ipv4)
+            .build()
+            .unwrap();
+        let options = Options { write: WriteOptions { precompute_psk: false, ..Default::default() }, ..Default::default() };
+
+        let contents = render_config(&network, &options).unwrap();
+        let expected = "[Security]\nPassphrase=foo_password\n\n[Settings]\nHidden=true\n\n\
+                        [IPv4]\nAddress=192.168.1.10\nGateway=192.168.1.1\nNetmask=255.255.255.0\n";
+        assert_eq!(expected, String::from_utf8(contents).unwrap());
+    }
+
+    #[test]
+    fn test_ensure_output_dir() {
+        let parent = tempfile::tempdir().unwrap();
+        let output = parent.path().join("iwd/networks");
+
+        assert!(matches!(ensure_output_dir(&output, false), Err(ConversionError::MissingOutputDir(_))));
+        ensure_output_dir(&output, true).unwrap();
+        assert_eq!(DIR_MODE, fs::metadata(&output).unwrap().mode() & 0o777);
+        ensure_output_dir(&output, false).unwrap();
+    }
+
+    #[test]
+    fn test_probe_output_dir() {
+        let output = tempfile::tempdir().unwrap();
+        probe_output_dir(output.path()).unwrap();
+        assert_eq!(0, fs::read_dir(output.path()).unwrap().count());
+
+        // Not a directory at all.
+        let file = output.path().join("file");
+        fs::write(&file, b"").unwrap();
+        assert!(probe_output_dir(&file).is_err());
+    }
+
+    #[test]
+    fn test_remap() {
+        let input = tempfile::tempdir().unwrap();
+        let old = input.path().join("old");
+        let raw = input.path().join("raw");
+        std::fs::write(&old, b"Connection=wireless\nESSID=OldCorp\nSecurity=wpa\nKey=foo_password\n").unwrap();
+        std::fs::write(&raw, format!("Connection=wireless\nESSID=OldCorp-2.4\nSecurity=wpa\nKey={}\n", "ab".repeat(32))).unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let remap = [("OldCorp", "NewCorp"), ("OldCorp-2.4", "NewCorp")].iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+        let options = Options { remap, ..Options::default() };
+        let files = [old.display().to_string(), raw.display().to_string()];
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &options);
+
+        assert!(results[0].1.is_ok());
+        let config = Ini::load_from_file(output.path().join("NewCorp.psk")).unwrap();
+        let psk = hex::encode(compute_psk(b"NewCorp", b"foo_password"));
+        assert_eq!(Some(psk.as_str()), config.get_from(Some("Security"), "PreSharedKey"));
+
+        assert!(matches!(&results[1].1, Err(ConversionError::RemappedRawPSK(from, to)) if from == "OldCorp-2.4" && to == "NewCorp"));
+    }
+
+    #[test]
+    fn test_output_suffix() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("psk");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let live = output.path().join("foo_network.psk");
+        std::fs::write(&live, "[Security]\nPassphrase=live\n").unwrap();
+
+        let options = Options { output_suffix: Some(".new".to_owned()), ..Options::default() };
+        let files = [profile.display().to_string()];
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &options);
+        assert_eq!(Outcome::Written, results[0].1.as_ref().unwrap().outcome);
+        assert!(output.path().join("foo_network.psk.new").exists());
+        assert_eq!("[Security]\nPassphrase=live\n", fs::read_to_string(&live).unwrap());
+
+        // Existing files are checked under the suffixed name.
+        std::fs::write(output.path().join("foo_network.psk.new"), "edited").unwrap();
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &options);
+        assert!(matches!(results[0].1, Err(ConversionError::FileExists(_))));
+    }
+
+    #[test]
+    fn test_merge() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("psk");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\nHidden=yes\n").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let existing = output.path().join("foo_network.psk");
+        std::fs::write(&existing, "[Security]\nPassphrase=old_password\nPreSharedKey=0123\n\n\
+                                   [Settings]\nHidden=false\nAutoConnect=false\n\n[IPv4]\nAddress=192.168.1.10\n").unwrap();
+
+        let options = Options { merge: true, write: WriteOptions { precompute_psk: false, ..WriteOptions::default() }, ..Options::default() };
+        let files = [profile.display().to_string()];
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &options);
+        let converted = results[0].1.as_ref().unwrap();
+        assert_eq!(Outcome::Overwritten, converted.outcome);
+
+        let replaced = vec!["Security.Passphrase".to_owned(), "Security.PreSharedKey".to_owned(), "Settings.Hidden".to_owned()];
+        assert_eq!(vec![Warning::MergedKeys("foo_network.psk".to_owned(), replaced)], converted.warnings);
+
+        let merged = Ini::load_from_file(&existing).unwrap();
+        assert_eq!(Some("foo_password"), merged.get_from(Some("Security"), "Passphrase"));
+        // A stale PSK would contradict the new passphrase.
+        assert_eq!(None, merged.get_from(Some("Security"), "PreSharedKey"));
+        assert_eq!(Some("true"), merged.get_from(Some("Settings"), "Hidden"));
+        assert_eq!(Some("false"), merged.get_from(Some("Settings"), "AutoConnect"));
+        assert_eq!(Some("192.168.1.10"), merged.get_from(Some("IPv4"), "Address"));
+
+        // Merging again changes nothing.
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &options);
+        assert_eq!(Outcome::Unchanged, results[0].1.as_ref().unwrap().outcome);
+    }
+
+    #[test]
+    fn test_write_file_atomic() {
+        let output = tempfile::tempdir().unwrap();
+        let entries = || {
+            let mut names: Vec<_> = fs::read_dir(output.path()).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+            names.sort();
+            names
+        };
+
+        write_file(output.path(), "foo.psk", b"first", false, &Options::default(), &mut Overwrite::Never).unwrap();
+        assert_eq!(vec!["foo.psk"], entries());
+
+        assert!(matches!(write_file(output.path(), "foo.psk", b"second", false, &Options::default(), &mut Overwrite::Never),
+            Err(ConversionError::FileExists(_))));
+        assert_eq!(vec!["foo.psk"], entries());
+        assert_eq!(b"first", &fs::read(output.path().join("foo.psk")).unwrap()[..]);
+
+        let options = Options { backup: true, ..Options::default() };
+        let (_, outcome, _) = write_file(output.path(), "foo.psk", b"second", false, &options, &mut Overwrite::Always).unwrap();
+        assert_eq!(Outcome::Overwritten, outcome);
+        assert_eq!(vec!["foo.psk", "foo.psk.bak"], entries());
+        assert_eq!(b"second", &fs::read(output.path().join("foo.psk")).unwrap()[..]);
+        assert_eq!(b"first", &fs::read(output.path().join("foo.psk.bak")).unwrap()[..]);
+        assert_eq!(0o600, fs::metadata(output.path().join("foo.psk")).unwrap().permissions().mode() & 0o777);
+    }
+
+    #[test]
+    fn test_outside_output_dir() {
+        let output = tempfile::tempdir().unwrap();
+        let options = Options::default();
+        assert!(matches!(write_file(output.path(), "../escape.psk", b"", false, &options, &mut Overwrite::Never),
+                         Err(ConversionError::OutsideOutputDir(_))));
+        assert!(matches!(write_file(output.path(), "/tmp/escape.psk", b"", false, &options, &mut Overwrite::Never),
+                         Err(ConversionError::OutsideOutputDir(_))));
+
+        let elsewhere = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(elsewhere.path(), output.path().join("secrets")).unwrap();
+        assert!(matches!(write_file(output.path(), "secrets/foo.psk", b"", false, &options, &mut Overwrite::Never),
+                         Err(ConversionError::OutsideOutputDir(_))));
+
+        // Slashes in SSIDs are hex-encoded, so the file stays in the output directory.
+        let network = Network::new("../../etc/passwd".to_owned(), Security::Open);
+        let name = network.iwd_file_name(HexCase::Lower);
+        assert_eq!("=2e2e2f2e2e2f6574632f706173737764.open", name);
+        let (path, _, _) = write_file(output.path(), &name, b"", false, &options, &mut Overwrite::Never).unwrap();
+        assert_eq!(output.path().join(name), path);
+    }
+
+    #[test]
+    fn test_mode() {
+        let output = tempfile::tempdir().unwrap();
+        let options = Options { mode: Some(0o640), ..Options::default() };
+        let (path, _, _) = write_file(output.path(), "foo.psk", b"contents", false, &options, &mut Overwrite::new(&options)).unwrap();
+        assert_eq!(0o640, std::fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(Ok(Owner { uid: Some(0), gid: Some(0) }), "root:root".parse());
+        assert_eq!(Ok(Owner { uid: Some(1000), gid: None }), "1000".parse());
+        assert_eq!(Ok(Owner { uid: None, gid: Some(0) }), ":0".parse());
+        assert!("no_such_user_hopefully".parse::<Owner>().is_err());
+        assert!(":".parse::<Owner>().is_err());
+
+        let passwd = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(passwd.path(), "root:x:0:0::/root:/bin/bash\niwd:x:971:971::/:/usr/bin/nologin\n").unwrap();
+        assert_eq!(Some("971".to_owned()), lookup_field(passwd.path(), "iwd", 2));
+        assert_eq!(Some("/root".to_owned()), lookup_field(passwd.path(), "root", 5));
+        assert_eq!(None, lookup_field(passwd.path(), "nobody", 2));
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let output = tempfile::tempdir().unwrap();
+        write_file(output.path(), "foo.psk", b"contents", false, &Options::default(), &mut Overwrite::Never).unwrap();
+
+        let (_, outcome, _) = write_file(output.path(), "foo.psk", b"contents", false, &Options::default(), &mut Overwrite::Never).unwrap();
+        assert_eq!(Outcome::Unchanged, outcome);
+
+        // Nothing is backed up, since nothing is replaced.
+        let options = Options { force: true, backup: true, ..Options::default() };
+        let (_, outcome, _) = write_file(output.path(), "foo.psk", b"contents", false, &options, &mut Overwrite::new(&options)).unwrap();
+        assert_eq!(Outcome::Unchanged, outcome);
+        assert!(!output.path().join("foo.psk.bak").exists());
+
+        // Stamped on another day, which doesn't count as a change.
+        let stamped = |date: &str| format!("# Generated by netctl2iwd 0.1.2 on {}\n# Network: Home\n[Security]\nPassphrase=foo_password\n", date);
+        fs::write(output.path().join("bar.psk"), stamped("2021-03-14")).unwrap();
+        let (_, outcome, _) = write_file(output.path(), "bar.psk", stamped("2021-03-15").as_bytes(), false, &Options::default(), &mut Overwrite::Never).unwrap();
+        assert_eq!(Outcome::Unchanged, outcome);
+        assert!(fs::read_to_string(output.path().join("bar.psk")).unwrap().contains("2021-03-14"));
+
+        let changed = stamped("2021-03-15").replace("Home", "Work");
+        let result = write_file(output.path(), "bar.psk", changed.as_bytes(), false, &Options::default(), &mut Overwrite::Never);
+        assert!(matches!(result, Err(ConversionError::FileExists(_))));
+    }
+
+    #[test]
+    fn test_backup() {
+        let output = tempfile::tempdir().unwrap();
+        let path = output.path().join("foo.psk");
+        std::fs::write(&path, b"first").unwrap();
+        std::fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+
+        let options = Options { force: true, backup: true, ..Options::default() };
+        write_file(output.path(), "foo.psk", b"second", false, &options, &mut Overwrite::new(&options)).unwrap();
+        write_file(output.path(), "foo.psk", b"third", false, &options, &mut Overwrite::new(&options)).unwrap();
+
+        assert_eq!(b"third".as_ref(), std::fs::read(&path).unwrap().as_slice());
+        assert_eq!(b"first".as_ref(), std::fs::read(output.path().join("foo.psk.bak")).unwrap().as_slice());
+        assert_eq!(b"second".as_ref(), std::fs::read(output.path().join("foo.psk.bak.1")).unwrap().as_slice());
+
+        let mode = std::fs::metadata(output.path().join("foo.psk.bak")).unwrap().permissions().mode();
+        assert_eq!(0o640, mode & 0o777);
+    }
+
+    #[test]
+    fn test_map_dirent() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("home"), b"Connection=wireless\n").unwrap();
+        std::fs::create_dir(dir.path().join("examples")).unwrap();
+
+        // A linked profile is read, but linked directories and link loops are not.
+        let shared = tempfile::tempdir().unwrap();
+        std::fs::write(shared.path().join("wlan0-work"), b"Connection=wireless\n").unwrap();
+        std::os::unix::fs::symlink(shared.path().join("wlan0-work"), dir.path().join("work")).unwrap();
+        std::os::unix::fs::symlink(shared.path(), dir.path().join("shared")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("loop-b"), dir.path().join("loop-a")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("loop-a"), dir.path().join("loop-b")).unwrap();
+
+        let mut entries: Vec<_> = read_dir(dir.path()).unwrap()
+            .filter_map(|e| map_dirent(dir.path(), e))
+            .collect();
+        entries.sort();
+        let expected: Vec<_> = ["home", "work"].iter().map(|p| dir.path().join(p).display().to_string()).collect();
+        assert_eq!(expected, entries);
+
+        let error = io::Error::other("I/O error");
+        assert_eq!(None, map_dirent(dir.path(), Err(error)));
+    }
+
+    #[test]
+    fn test_exclude_profiles() {
+        let mut profiles = vec!["/etc/netctl/home".to_owned(), "/etc/netctl/home.bak".to_owned(), "/etc/netctl/template-work".to_owned()];
+        let patterns = [Pattern::new("*.bak").unwrap(), Pattern::new("template-*").unwrap(), Pattern::new("netctl").unwrap()];
+
+        assert_eq!(2, exclude_profiles(&mut profiles, &patterns));
+        assert_eq!(vec!["/etc/netctl/home".to_owned()], profiles);
+    }
+
+    #[test]
+    fn test_walk_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("site/office")).unwrap();
+        std::fs::write(dir.path().join("home"), b"").unwrap();
+        std::fs::write(dir.path().join("site/office/corp"), b"").unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("site/loop")).unwrap();
+
+        let mut profiles = Vec::new();
+        walk_dir(dir.path(), &mut HashSet::new(), &mut profiles).unwrap();
+        profiles.sort();
+
+        let expected: Vec<_> = ["home", "site/office/corp"].iter()
+            .map(|p| dir.path().join(p).display().to_string())
+            .collect();
+        assert_eq!(expected, profiles);
+    }
+
+    #[test]
+    fn test_expand_input() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &["home-1", "home-2", "work"] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let pattern = dir.path().join("home-*").display().to_string();
+        let expected: Vec<_> = ["home-1", "home-2"].iter().map(|p| dir.path().join(p).display().to_string()).collect();
+        assert_eq!(expected, expand_input(&pattern).unwrap());
+
+        let pattern = dir.path().join("office-?").display().to_string();
+        assert!(matches!(expand_input(&pattern), Err(ConversionError::NoMatches(_))));
+        assert_eq!(vec!["missing"], expand_input("missing").unwrap());
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary { converted: 12, failed: 1, skipped: 2, ..Summary::default() };
+        assert_eq!("12 converted, 2 skipped (already exist), 1 failed", summary.to_string());
+        assert_eq!(15, summary.total());
+
+        let summary = Summary { converted: 3, overwritten: 1, ..Summary::default() };
+        assert_eq!("3 converted (1 overwritten), 0 skipped (already exist), 0 failed", summary.to_string());
+    }
+
+    #[test]
+    fn test_convert_files_duplicates() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("open");
+        let link = input.path().join("link");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=foo_network\nSecurity=none\n").unwrap();
+        unix::fs::symlink(&profile, &link).unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let files = [profile.display().to_string(), link.display().to_string(), profile.display().to_string()];
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &Options::default());
+
+        assert_eq!(1, results.len());
+        assert_eq!(files[0], results[0].0);
+        assert!(results[0].1.is_ok());
+    }
+
+    #[test]
+    fn test_write_failures() {
+        let results: Vec<Conversion> = vec![
+            ("open".to_owned(), Ok(Converted { records: Vec::new(), warnings: Vec::new(), details: Vec::new(), outcome: Outcome::Written, backups: Vec::new() })),
+            ("wired".to_owned(), Err(ConversionError::Wired)),
+            ("existing".to_owned(), Err(ConversionError::FileExists(io::Error::from(ErrorKind::AlreadyExists)))),
+            ("short".to_owned(), Err(ConversionError::EmptyKey("Key".to_owned()))),
+        ];
+
+        let mut failures = Vec::new();
+        write_failures(&mut failures, &results).unwrap();
+        let expected = format!("wired\t{}\nshort\tKey is empty\n", ConversionError::Wired);
+        assert_eq!(expected, String::from_utf8(failures).unwrap());
+
+        let mut failures = Vec::new();
+        write_failures(&mut failures, &[]).unwrap();
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn test_convert_files_results() {
+        let input = tempfile::tempdir().unwrap();
+        let good = input.path().join("good");
+        let wired = input.path().join("wired");
+        std::fs::write(&good, b"Connection=wireless\nESSID=foo_network\nSecurity=none\n").unwrap();
+        std::fs::write(&wired, b"Connection=ethernet\nInterface=eth0\n").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let files = [good.display().to_string(), wired.display().to_string()];
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &Options::default());
+
+        assert_eq!(files[0], results[0].0);
+        assert_eq!(Outcome::Written, results[0].1.as_ref().unwrap().outcome);
+        assert!(matches!(results[1].1, Err(ConversionError::Wired)));
+        assert!(output.path().join("foo_network.open").exists());
+
+        let summary = Summary::from(&results[..]);
+        assert_eq!((1, 1), (summary.converted, summary.failed));
+        assert_eq!(2, summary.records.len());
+    }
+
+    #[test]
+    fn test_convert_tar() {
+        let input = tempfile::tempdir().unwrap();
+        let archive = input.path().join("netctl.tar.gz");
+
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(File::create(&archive).unwrap(), Default::default()));
+        let mut append = |path: &str, contents: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, path, contents).unwrap();
+        };
+        append("netctl/home", b"Connection=wireless\nESSID=foo_network\nSecurity=none\n");
+        append("netctl/wired", b"Connection=ethernet\nInterface=eth0\n");
+        append("netctl/examples/wireless-open", b"Connection=wireless\nESSID=example\nSecurity=none\n");
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let archive = archive.to_str().unwrap();
+        let results = convert_tar(archive, output.path().to_str().unwrap(), &Options::default()).unwrap();
+
+        assert_eq!(2, results.len());
+        assert_eq!(format!("{}:netctl/home", archive), results[0].0);
+        assert!(results[0].1.is_ok());
+        assert!(matches!(results[1].1, Err(ConversionError::Wired)));
+        assert!(output.path().join("foo_network.open").exists());
+
+        let options = Options { recursive: true, ..Default::default() };
+        let counts = count_tar(archive, &options).unwrap();
+        assert_eq!(Counts { convertible: 2, skipped: 1, errors: 0 }, counts);
+    }
+
+    #[test]
+    fn test_expand_path() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(home, expand_path("~").unwrap());
+        assert_eq!(format!("{}/sub", home), expand_path("~/sub").unwrap());
+        assert_eq!("/root/iwd", expand_path("~root/iwd").unwrap());
+        assert_eq!(format!("{0}/a/{0}b", home), expand_path("$HOME/a/${HOME}b").unwrap());
+        assert_eq!("/etc/netctl/a~$", expand_path("/etc/netctl/a~$").unwrap());
+
+        let error = expand_path("$NETCTL2IWD_UNDEFINED/profiles").unwrap_err();
+        assert_eq!("Environment variable NETCTL2IWD_UNDEFINED is not set", error);
+        assert!(expand_path("${HOME").is_err());
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2019, 3, 1), civil_from_days(17956));
+    }
+
+    #[test]
+    fn test_base_settings() {
+        let mut options = Options::default();
+        options.base_settings.all = Some(Ini::load_from_str("[Settings]\nAutoConnect=true\nHidden=false\n").unwrap());
+        options.base_settings.per_security.insert(
+            "open".to_owned(), Ini::load_from_str("[Settings]\nAutoConnect=false\n").unwrap());
+        options.base_settings.per_security.insert(
+            "psk".to_owned(), Ini::load_from_str("[Security]\nPassphrase=baseline\n").unwrap());
+
+        let open = Network::new("open_network".to_string(), Security::Open);
+        let contents = render_config(&open, &options).unwrap();
+        let config = Ini::load_from_str(&String::from_utf8(contents).unwrap()).unwrap();
+        assert_eq!(config.get_from(Some("Settings"), "AutoConnect"), Some("false"));
+        assert_eq!(config.get_from(Some("Settings"), "Hidden"), Some("false"));
+        assert_eq!(config.get_from(Some("Security"), "Passphrase"), None);
+
+        let psk = Network::new("foo_network".to_string(),
+                               Security::PSK(PSKSecurity::Password("foo_password".to_string())));
+        let contents = render_config(&psk, &options).unwrap();
+        let config = Ini::load_from_str(&String::from_utf8(contents).unwrap()).unwrap();
+        assert_eq!(config.get_from(Some("Settings"), "AutoConnect"), Some("true"));
+        assert_eq!(config.get_from(Some("Security"), "Passphrase"), Some("foo_password"));
+    }
+
+    #[test]
+    fn test_resolve_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("home"), b"Connection=wireless\n").unwrap();
+
+        assert_eq!(dir.path().join("home"), resolve_profile(dir.path(), "home").unwrap());
+        assert!(matches!(resolve_profile(dir.path(), "work"), Err(ConversionError::ProfileNotFound(_))));
+        assert!(matches!(resolve_profile(dir.path(), "../home"), Err(ConversionError::ProfileNotFound(_))));
+    }
+
+    #[test]
+    fn test_layouts() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("foo");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=foo_network\nKey=foo_password\nSecurity=wpa\n").unwrap();
+
+        let classic = tempfile::tempdir().unwrap();
+        write_files(generate(&profile, &Options::default()).unwrap().0, classic.path().to_str().unwrap(), &Options::default(), &mut Overwrite::Never, &mut Vec::new()).unwrap();
+        let config = Ini::load_from_file(classic.path().join("foo_network.psk")).unwrap();
+        assert_eq!(config.get_from(Some("Security"), "Passphrase"), Some("foo_password"));
+        assert!(!classic.path().join(SECRETS_DIR).exists());
+
+        let split = tempfile::tempdir().unwrap();
+        let options = Options { layout: Layout::Split, split_by_security: true, mode: Some(0o644), ..Options::default() };
+        write_files(generate(&profile, &options).unwrap().0, split.path().to_str().unwrap(), &options, &mut Overwrite::new(&options), &mut Vec::new()).unwrap();
+        let config_path = split.path().join("psk").join("foo_network.psk");
+        let config = Ini::load_from_file(&config_path).unwrap();
+        assert!(config.section(Some("Security")).is_none());
+        assert_eq!(0o644, std::fs::metadata(&config_path).unwrap().permissions().mode() & 0o777);
+        // The secrets stay private, whatever the mode.
+        let secrets_path = split.path().join("psk").join(SECRETS_DIR).join("foo_network.psk");
+        let secrets = Ini::load_from_file(&secrets_path).unwrap();
+        assert_eq!(secrets.get_from(Some("Security"), "Passphrase"), Some("foo_password"));
+        assert_eq!(0o600, std::fs::metadata(&secrets_path).unwrap().permissions().mode() & 0o777);
+    }
+
+    #[test]
+    fn test_convert_multiple_essids() {
+        let input = tempfile::tempdir().unwrap();
+        let profile = input.path().join("corp");
+        std::fs::write(&profile, b"Connection=wireless\nESSID=('Corp' 'Corp-5G' 'Corp')\nKey=foo_password\nSecurity=wpa\n").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        write_files(generate(&profile, &Options::default()).unwrap().0, output.path().to_str().unwrap(), &Options::default(), &mut Overwrite::Never, &mut Vec::new()).unwrap();
+
+        let mut files: Vec<_> = std::fs::read_dir(output.path()).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(vec!["Corp-5G.psk", "Corp.psk"], files);
+
+        for name in files {
+            let config = Ini::load_from_file(output.path().join(name)).unwrap();
+            assert_eq!(config.get_from(Some("Security"), "Passphrase"), Some("foo_password"));
+        }
+    }
+
+    #[test]
+    fn test_generate_all_order() {
+        let input = tempfile::tempdir().unwrap();
+        let profiles: Vec<_> = (0..8).map(|i| {
+            let profile = input.path().join(format!("net{}", i));
+            std::fs::write(&profile, format!("Connection=wireless\nESSID=net{}\nKey=foo_password\nSecurity=wpa\n", i)).unwrap();
+            profile
+        }).collect();
+
+        let options = Options { jobs: 3, ..Options::default() };
+        let ssids: Vec<_> = generate_all(&profiles, &options).into_iter()
+            .map(|result| result.unwrap().0.remove(0).ssid)
+            .collect();
+        let expected: Vec<_> = (0..8).map(|i| format!("net{}", i)).collect();
+        assert_eq!(expected, ssids);
+    }
+
+    #[test]
+    fn test_copy_certs() {
+        let input = tempfile::tempdir().unwrap();
+        std::fs::write(input.path().join("ca.pem"), b"certificate").unwrap();
+        std::fs::write(input.path().join("user.key"), b"private key").unwrap();
+        let profile = input.path().join("eduroam");
+        std::fs::write(&profile, format!("Connection=wireless\nSecurity=wpa-configsection\n\
+            WPAConfigSection=('ssid=\"eduroam\"' 'key_mgmt=WPA-EAP' 'eap=TLS' 'identity=\"user\"' \
+            'ca_cert=\"{0}/ca.pem\"' 'private_key=\"{0}/user.key\"')\n", input.path().display())).unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let options = Options { cert_dir: Some(output.path().to_owned()), ..Options::default() };
+        let (files, _, _) = generate(&profile, &options).unwrap();
+        let names: Vec<_> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(vec!["ca.pem", "user.key", "eduroam.8021x"], names);
+
+        write_files(files, output.path().to_str().unwrap(), &options, &mut Overwrite::new(&options), &mut Vec::new()).unwrap();
+        let key_path = output.path().join("user.key");
+        assert_eq!(b"private key".as_ref(), std::fs::read(&key_path).unwrap().as_slice());
+        assert_eq!(0o600, std::fs::metadata(&key_path).unwrap().permissions().mode() & 0o777);
+
+        // Private keys stay private, whatever the mode of the other files.
+        let shared = tempfile::tempdir().unwrap();
+        let options = Options { cert_dir: Some(shared.path().to_owned()), mode: Some(0o644), ..Options::default() };
+        let (files, _, _) = generate(&profile, &options).unwrap();
+        write_files(files, shared.path().to_str().unwrap(), &options, &mut Overwrite::new(&options), &mut Vec::new()).unwrap();
+        let mode = |name: &str| std::fs::metadata(shared.path().join(name)).unwrap().permissions().mode() & 0o777;
+        assert_eq!((0o600, 0o644, 0o644), (mode("user.key"), mode("ca.pem"), mode("eduroam.8021x")));
+
+        let config = Ini::load_from_file(output.path().join("eduroam.8021x")).unwrap();
+        let ca_path = output.path().join("ca.pem").display().to_string();
+        assert_eq!(Some(ca_path.as_str()), config.get_from(Some("Security"), "EAP-TLS-CACert"));
+
+        std::fs::remove_file(input.path().join("ca.pem")).unwrap();
+        assert!(matches!(generate(&profile, &options), Err(ConversionError::UnreadableCertificate(..))));
+    }
+
+    #[test]
+    fn test_find_collisions() {
+        let file = |name: &str, source: &str, contents: &[u8]| OutputFile {
+            name: name.to_owned(),
+            contents: contents.to_owned(),
+            ssid: String::new(),
+            security: "psk",
+            source: source.to_owned(),
+            secret: false,
+        };
+        let files = [file("home.psk", "home", b"new"), file("work.psk", "work", b"new"), file("home.psk", "home-old", b"old")];
+
+        assert_eq!(vec![("home.psk", "home", "home-old")], find_collisions(files.iter()));
+        assert!(find_collisions(files[..2].iter()).is_empty());
+
+        let shared = [file("ca.pem", "home", b"certificate"), file("ca.pem", "work", b"certificate")];
+        assert!(find_collisions(shared.iter()).is_empty());
+    }
+
+    #[test]
+    fn test_write_stream_order() {
+        let file = |name: &str, ssid: &str, security: &'static str, source: &str| OutputFile {
+            name: name.to_owned(),
+            contents: Vec::new(),
+            ssid: ssid.to_owned(),
+            security,
+            source: source.to_owned(),
+            secret: false,
+        };
+        let files = || vec![
+            file("b.psk", "b", "psk", "/etc/netctl/1"),
+            file("=63.open", "c", "open", "/etc/netctl/2"),
+            file("a.psk", "a", "psk", "/etc/netctl/3"),
+        ];
+
+        let order = |sort| {
+            let mut stream = Vec::new();
+            write_stream(&mut stream, files(), sort).unwrap();
+
+            let mut reader = stream.as_slice();
+            let mut names = Vec::new();
+            while let Some((name, _, _)) = stream::read_entry(&mut reader).unwrap() {
+                names.push(name);
+            }
+            names
+        };
+
+        assert_eq!(vec!["=63.open", "a.psk", "b.psk"], order(SortKey::Name));
+        assert_eq!(vec!["a.psk", "b.psk", "=63.open"], order(SortKey::Ssid));
+        assert_eq!(vec!["=63.open", "a.psk", "b.psk"], order(SortKey::Security));
+        assert_eq!(vec!["b.psk", "=63.open", "a.psk"], order(SortKey::Source));
+    }
+
+    #[test]
+    fn test_only_security() {
+        let input = tempfile::tempdir().unwrap();
+        let open = input.path().join("open");
+        let psk = input.path().join("psk");
+        std::fs::write(&open, b"Connection=wireless\nESSID=open_network\nSecurity=none\n").unwrap();
+        std::fs::write(&psk, b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n").unwrap();
+
+        let output = tempfile::tempdir().unwrap();
+        let options = Options { only_security: Some("open".to_owned()), ..Options::default() };
+        let files = [open.display().to_string(), psk.display().to_string()];
+        let results = convert_files(files.iter(), output.path().to_str().unwrap(), &options);
+
+        assert!(results[0].1.is_ok());
+        assert!(matches!(results[1].1, Err(ConversionError::OtherSecurity("psk"))));
+        assert!(output.path().join("open_network.open").exists());
+        assert!(!output.path().join("foo_network.psk").exists());
+
+        let summary = Summary::from(&results[..]);
+        assert_eq!((1, 0, 1), (summary.converted, summary.failed, summary.filtered));
+        assert_eq!("1 converted, 0 skipped (already exist), 0 failed, 1 of other security types", summary.to_string());
+    }
+
+    #[test]
+    fn test_count_files() {
+        let input = tempfile::tempdir().unwrap();
+        let profiles: &[(&str, &[u8])] = &[
+            ("open", b"Connection=wireless\nESSID=open_network\nSecurity=none\n"),
+            ("psk", b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n"),
+            ("wired", b"Connection=ethernet\nInterface=eth0\n"),
+            ("short", b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=short\n"),
+        ];
+        for (name, contents) in profiles {
+            std::fs::write(input.path().join(name), contents).unwrap();
+        }
+
+        let files = profiles.iter().map(|(name, _)| input.path().join(name));
+        let counts = count_files(files, &Options::default());
+        assert_eq!(Counts { convertible: 2, skipped: 1, errors: 1 }, counts);
+    }
+}
@@ -0,0 +1,163 @@
+//! Typed IP configuration of a network, independent of any configuration format.
+
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// IP configuration for a single address family.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub enum IpConfig {
+    /// Automatic configuration, which is what iwd does by default.
+    #[default]
+    Dhcp,
+    /// Stateless address autoconfiguration, for IPv6 only.
+    Stateless,
+    Static(StaticConfig),
+    /// No configuration for this address family at all.
+    Disabled,
+}
+
+/// Statically configured addresses and servers.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct StaticConfig {
+    pub addresses: Vec<Address>,
+    pub gateway: Option<IpAddr>,
+    pub dns: Vec<IpAddr>,
+    pub routes: Vec<Route>,
+    /// The DNS domain of the network, which iwd only has for IPv4.
+    pub domain_name: Option<String>,
+}
+
+/// An address with its prefix length.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Address {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+impl Address {
+    /// The netmask corresponding to the prefix, for IPv4 addresses.
+    pub fn netmask(&self) -> Option<Ipv4Addr> {
+        match self.ip {
+            IpAddr::V4(_) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                Some(Ipv4Addr::from(mask))
+            }
+            IpAddr::V6(_) => None,
+        }
+    }
+}
+
+/// The prefix length of an IPv4 netmask like `255.255.255.0`, if it is a valid netmask.
+pub fn prefix_from_netmask(netmask: Ipv4Addr) -> Option<u8> {
+    let mask = u32::from(netmask);
+    let prefix = mask.leading_ones();
+
+    // All ones must come before all zeroes.
+    if mask.checked_shl(prefix).unwrap_or(0) == 0 {
+        Some(prefix as u8)
+    } else {
+        None
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    /// Parse an address in CIDR notation. A bare address is a single host address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, prefix) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (s, None),
+        };
+
+        let ip = IpAddr::from_str(ip).map_err(|_| format!("Invalid address: {}", s))?;
+        let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length: {}", s))?,
+            None => max_prefix,
+        };
+
+        Ok(Address { ip, prefix })
+    }
+}
+
+/// A static route, as in `192.168.2.0/24 via 192.168.1.2`.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Route {
+    pub destination: Address,
+    pub via: Option<IpAddr>,
+}
+
+impl Display for Route {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.destination)?;
+        if let Some(via) = self.via {
+            write!(f, " via {}", via)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let destination = parts.next().ok_or_else(|| "Empty route".to_owned())?.parse()?;
+
+        let via = match (parts.next(), parts.next(), parts.next()) {
+            (None, _, _) => None,
+            (Some("via"), Some(via), None) => Some(IpAddr::from_str(via).map_err(|_| format!("Invalid route: {}", s))?),
+            _ => return Err(format!("Invalid route: {}", s)),
+        };
+
+        Ok(Route { destination, via })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        let address: Address = "192.168.1.10/24".parse().unwrap();
+        assert_eq!(IpAddr::from([192, 168, 1, 10]), address.ip);
+        assert_eq!(24, address.prefix);
+        assert_eq!(Some(Ipv4Addr::new(255, 255, 255, 0)), address.netmask());
+
+        let address: Address = "2001:db8::1".parse().unwrap();
+        assert_eq!(128, address.prefix);
+        assert_eq!("2001:db8::1/128", address.to_string());
+
+        assert!("192.168.1.10/33".parse::<Address>().is_err());
+        assert!("not an address".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn test_prefix_from_netmask() {
+        assert_eq!(Some(24), prefix_from_netmask(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(Some(32), prefix_from_netmask(Ipv4Addr::new(255, 255, 255, 255)));
+        assert_eq!(Some(0), prefix_from_netmask(Ipv4Addr::new(0, 0, 0, 0)));
+        assert_eq!(None, prefix_from_netmask(Ipv4Addr::new(255, 0, 255, 0)));
+    }
+
+    #[test]
+    fn test_parse_route() {
+        let route: Route = "192.168.2.0/24 via 192.168.1.2".parse().unwrap();
+        assert_eq!("192.168.2.0/24 via 192.168.1.2", route.to_string());
+        assert!("192.168.2.0/24 through 192.168.1.2".parse::<Route>().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Reading iwd network files back into networks, for converting them to netctl profiles.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::Path;
+
+use ini::Ini;
+
+use crate::convert::ConversionError;
+use crate::ip::prefix_from_netmask;
+use crate::ip::Address;
+use crate::ip::IpConfig;
+use crate::ip::StaticConfig;
+use crate::networks::Network;
+use crate::networks::PSKSecurity;
+use crate::networks::Security;
+use crate::networks::Ssid;
+
+/// Read an iwd network file, whose name tells the SSID and security type.
+pub fn read_network(path: &Path) -> Result<Network, ConversionError> {
+    let name = path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| ConversionError::ParseError(format!("Invalid iwd file name: {}", path.display()), None))?;
+    let contents = fs::read_to_string(path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => ConversionError::ProfileNotFound(path.to_owned()),
+        _ => ConversionError::from(e),
+    })?;
+    let config = Ini::load_from_str(&contents)?;
+
+    parse_network(name, &config)
+}
+
+/// Build a network from the name and contents of an iwd network file.
+///
+/// Only open and PSK networks are supported.
+pub fn parse_network(name: &str, config: &Ini) -> Result<Network, ConversionError> {
+    let invalid_name = || ConversionError::ParseError(format!("Invalid iwd file name: {}", name), None);
+    let (ssid, extension) = name.rsplit_once('.').ok_or_else(invalid_name)?;
+
+    // The inverse of `Network::iwd_file_name`.
+    let ssid = match ssid.strip_prefix('=') {
+        Some(hex) => Ssid::from(hex::decode(hex).map_err(|_| invalid_name())?),
+        None => Ssid::from(ssid),
+    };
+
+    let get = |section: &str, key: &str| config.get_from(Some(section), key);
+
+    let security = match extension {
+        "open" => Security::Open,
+        "psk" => match (get("Security", "Passphrase"), get("Security", "PreSharedKey")) {
+            (Some(passphrase), _) => Security::PSK(PSKSecurity::Password(passphrase.to_owned())),
+            (None, Some(psk)) => Security::PSK(PSKSecurity::PSK(psk.to_owned())),
+            (None, None) => return Err(ConversionError::MissingKeys),
+        },
+        "8021x" => return Err(ConversionError::Unsupported),
+        _ => return Err(invalid_name()),
+    };
+
+    let hidden = get("Settings", "Hidden") == Some("true");
+    let auto_connect = get("Settings", "AutoConnect") != Some("false");
+
+    Network::builder()
+        .ssid(ssid)
+        .security(security)
+        .hidden(hidden)
+        .auto_connect(auto_connect)
+        .ipv4(parse_ipv4(config)?)
+        .build()
+}
+
+/// Read the static IPv4 configuration, if any.
+fn parse_ipv4(config: &Ini) -> Result<IpConfig, ConversionError> {
+    let invalid = ConversionError::InvalidIpConfig;
+    let get = |key: &str| config.get_from(Some("IPv4"), key);
+
+    let address = match get("Address") {
+        Some(address) => address,
+        None => return Ok(IpConfig::Dhcp),
+    };
+
+    let prefix = match get("Netmask") {
+        Some(netmask) => netmask.parse().ok()
+            .and_then(prefix_from_netmask)
+            .ok_or_else(|| invalid(format!("Invalid netmask: {}", netmask)))?,
+        // Which is also what iwd uses without a netmask.
+        None => 24,
+    };
+    let ip = address.parse().map_err(|_| invalid(format!("Invalid address: {}", address)))?;
+
+    let gateway = get("Gateway")
+        .map(|g| g.parse::<IpAddr>().map_err(|_| invalid(format!("Invalid gateway: {}", g))))
+        .transpose()?;
+
+    let dns = get("DNS").unwrap_or("").split_whitespace()
+        .map(|d| d.parse::<IpAddr>().map_err(|_| invalid(format!("Invalid DNS server: {}", d))))
+        .collect::<Result<_, _>>()?;
+
+    let domain_name = get("DomainName").map(str::to_owned);
+
+    Ok(IpConfig::Static(StaticConfig { addresses: vec![Address { ip, prefix }], gateway, dns, routes: Vec::new(), domain_name }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::networks::HexCase;
+    use crate::networks::WriteOptions;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let network = Network::builder()
+            .ssid("foo network!")
+            .security(Security::PSK(PSKSecurity::Password("foo_password".to_owned())))
+            .hidden(true)
+            .auto_connect(false)
+            .build()
+            .unwrap();
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        let name = network.iwd_file_name(HexCase::Lower);
+
+        assert_eq!(network, parse_network(&name, &config).unwrap());
+
+        let network = Network::new("=cafe".to_owned(), Security::Open);
+        let name = network.iwd_file_name(HexCase::Lower);
+        assert_eq!("=cafe", parse_network(&name, &Ini::new()).unwrap().ssid());
+
+        assert_eq!(&[0xff, 0x00], parse_network("=ff00.open", &Ini::new()).unwrap().ssid().as_bytes());
+    }
+
+    #[test]
+    fn test_parse_psk() {
+        let config = Ini::load_from_str(&format!("[Security]\nPreSharedKey={}\n", "0123".repeat(16))).unwrap();
+        let network = parse_network("foo_network.psk", &config).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::PSK("0123".repeat(16))), network.security());
+
+        let config = Ini::load_from_str("[Security]\nPreSharedKey=0123\n").unwrap();
+        assert!(matches!(parse_network("foo_network.psk", &config), Err(ConversionError::InvalidPSK)));
+
+        assert!(matches!(parse_network("foo_network.psk", &Ini::new()), Err(ConversionError::MissingKeys)));
+        assert!(matches!(parse_network("eduroam.8021x", &Ini::new()), Err(ConversionError::Unsupported)));
+    }
+
+    #[test]
+    fn test_parse_ipv4() {
+        let config = Ini::load_from_str("[IPv4]\nAddress=192.168.1.10\nNetmask=255.255.255.0\nGateway=192.168.1.1\n\
+                                         DNS=192.168.1.1 9.9.9.9\nDomainName=home.example\n").unwrap();
+        let network = parse_network("foo_network.open", &config).unwrap();
+
+        let static_config = match network.ipv4() {
+            IpConfig::Static(static_config) => static_config,
+            other => panic!("Expected a static configuration, got {:?}", other),
+        };
+        assert_eq!("192.168.1.10/24", static_config.addresses[0].to_string());
+        assert_eq!(2, static_config.dns.len());
+        assert_eq!(Some("home.example"), static_config.domain_name.as_deref());
+    }
+}
@@ -0,0 +1,23 @@
+//! Convert netctl profiles to iwd configurations.
+//!
+//! The binary is a thin wrapper around this library, which can also be used to construct and
+//! write iwd networks programmatically, or only for `compute_psk` and `Network::iwd_file_name`.
+
+pub mod convert;
+pub mod ip;
+pub mod iwd;
+pub mod netctl;
+pub mod networks;
+#[cfg(feature = "dbus")]
+pub mod reload;
+pub mod stream;
+
+pub use crate::convert::convert_reader;
+pub use crate::convert::write_network;
+pub use crate::convert::ConversionError;
+pub use crate::networks::compute_psk;
+pub use crate::networks::HexCase;
+pub use crate::networks::Network;
+pub use crate::networks::PSKSecurity;
+pub use crate::networks::Security;
+pub use crate::networks::Ssid;
@@ -1,45 +1,1126 @@
 #[macro_use]
 extern crate clap;
 
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::exit;
+use std::str::FromStr;
+
+use clap::AppSettings;
 use clap::Arg;
+use clap::ArgGroup;
 use clap::ArgMatches;
+use clap::ErrorKind;
+use clap::SubCommand;
+use glob::Pattern;
+use ini::Ini;
 
-use crate::convert::convert_dir;
-use crate::convert::convert_files;
-
-mod convert;
-mod networks;
+use netctl2iwd::convert::check_files;
+use netctl2iwd::convert::convert_files;
+use netctl2iwd::convert::convert_tar;
+use netctl2iwd::convert::count_files;
+use netctl2iwd::convert::count_tar;
+use netctl2iwd::convert::ensure_output_dir;
+use netctl2iwd::convert::exclude_profiles;
+use netctl2iwd::convert::expand_input;
+use netctl2iwd::convert::expand_path;
+use netctl2iwd::convert::list_dir;
+use netctl2iwd::convert::list_files;
+use netctl2iwd::convert::lock_output;
+use netctl2iwd::convert::probe_output_dir;
+use netctl2iwd::convert::resolve_profile;
+use netctl2iwd::convert::write_failures;
+use netctl2iwd::convert::BaseSettings;
+use netctl2iwd::convert::Check;
+use netctl2iwd::convert::Conversion;
+use netctl2iwd::convert::ConversionError;
+use netctl2iwd::convert::Listing;
+use netctl2iwd::convert::Log;
+use netctl2iwd::convert::Options;
+use netctl2iwd::convert::OutputFormat;
+use netctl2iwd::convert::Outcome;
+use netctl2iwd::convert::Owner;
+use netctl2iwd::convert::STREAM_OUTPUT;
+use netctl2iwd::convert::Summary;
+use netctl2iwd::iwd;
+use netctl2iwd::netctl::write_profile;
+use netctl2iwd::netctl::ParseOptions;
+use netctl2iwd::networks::HexCase;
+use netctl2iwd::networks::PBKDF2_ITERATIONS;
+use netctl2iwd::networks::WriteOptions;
+use netctl2iwd::stream;
 
 /// Default install location of network files.
 const DEFAULT_INSTALL_PATH: &str = "/var/lib/iwd";
 
+/// Default location of netctl profiles.
+const DEFAULT_NETCTL_PATH: &str = "/etc/netctl";
+
+/// Exit statuses of the program.
+///
+/// Scripts may depend on these, so existing values must not change.
+#[derive(Clone, Copy, Debug)]
+enum ExitStatus {
+    /// All profiles were converted.
+    Success = 0,
+    /// At least one profile failed to convert.
+    Failures = 1,
+    /// The command line arguments, or the files and directories they name, were invalid.
+    Usage = 2,
+    /// The input directory could not be read.
+    InputUnreadable = 3,
+    /// There were no profiles to convert.
+    NothingToDo = 4,
+}
+
+impl From<&Summary> for ExitStatus {
+    fn from(summary: &Summary) -> Self {
+        if summary.total() == 0 {
+            ExitStatus::NothingToDo
+        } else if summary.failed > 0 || summary.skipped > 0 {
+            // Existing files have always counted as failures.
+            ExitStatus::Failures
+        } else {
+            ExitStatus::Success
+        }
+    }
+}
+
+/// ANSI escape sequences for the colors of reported results.
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// When to colorize reported results.
+#[derive(Clone, Copy, Debug)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+/// An output stream of the program.
+#[derive(Clone, Copy, Debug)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Color {
+    /// Colorize a message that will be written to the given stream, if enabled for it.
+    fn paint(self, color: &str, message: String, stream: Stream) -> String {
+        let enabled = match self {
+            Color::Always => true,
+            Color::Never => false,
+            // See https://no-color.org.
+            Color::Auto => env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && match stream {
+                Stream::Stdout => io::stdout().is_terminal(),
+                Stream::Stderr => io::stderr().is_terminal(),
+            },
+        };
+
+        if enabled {
+            format!("{}{}{}", color, message, RESET)
+        } else {
+            message
+        }
+    }
+}
+
 fn main() {
-    let matches: ArgMatches = app_from_crate!()
-        .arg(Arg::with_name("input")
+    exit(run() as i32);
+}
+
+fn run() -> ExitStatus {
+    let app = app_from_crate!()
+        // Only an exact subcommand name as the first argument selects one, so invocations with
+        // profiles keep working. Profiles always go to `input` before an external subcommand; the
+        // setting only stops clap from rejecting profiles named like a subcommand.
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::AllowExternalSubcommands)
+        .args(&input_args())
+        .args(&convert_args())
+        .args(&mode_args())
+        .group(overwrite_group())
+        .subcommand(SubCommand::with_name("convert")
+            .about("Convert profiles to iwd files, which is also what happens without a subcommand")
+            .args(&input_args())
+            .args(&convert_args())
+            .group(overwrite_group()))
+        .subcommand(SubCommand::with_name("list")
+            .about("Print a table of the networks in the profiles and the files they would be written to")
+            .args(&input_args())
+            .arg(hex_case_arg()))
+        .subcommand(SubCommand::with_name("check")
+            .about("Report the problems converting the profiles would run into, without writing anything")
+            .args(&input_args()));
+
+    let top: ArgMatches = match app.get_matches_safe() {
+        Ok(matches) => matches,
+        Err(e) => match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                return ExitStatus::Usage;
+            }
+        },
+    };
+
+    // Without a subcommand, the top-level arguments convert, unless an older mode flag is given.
+    let (command, matches) = match top.subcommand() {
+        (command, Some(matches)) => (command, matches),
+        _ if top.is_present("list") => ("list", &top),
+        _ if top.is_present("check") => ("check", &top),
+        _ => ("convert", &top),
+    };
+
+    // The shell doesn't expand ~ after =, or in quoted arguments.
+    let path_arg = |name: &str| matches.value_of(name).map(expand_path).transpose();
+    let dirs = matches.values_of("dir").unwrap_or_default().map(expand_path).collect::<Result<Vec<_>, _>>();
+    let (output, dirs, netctl_dir) = match (path_arg("output"), dirs, path_arg("netctl-dir")) {
+        // Only `convert` has an output directory.
+        (Ok(output), Ok(dirs), Ok(netctl_dir)) => (output.unwrap_or_else(|| DEFAULT_INSTALL_PATH.to_owned()), dirs, netctl_dir.unwrap()),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("Invalid path: {}", e);
+            return ExitStatus::Usage;
+        }
+    };
+    let output = output.as_str();
+
+    let base_settings = match base_settings(matches) {
+        Ok(base_settings) => base_settings,
+        Err(status) => return status,
+    };
+
+    let secrets = match matches.value_of("secrets").map(|path| load_map(path, "secrets")).transpose() {
+        Ok(secrets) => secrets.unwrap_or_default(),
+        Err(status) => return status,
+    };
+
+    let remap = match matches.value_of("remap").map(|path| load_map(path, "SSID remapping")).transpose() {
+        Ok(remap) => remap.unwrap_or_default(),
+        Err(status) => return status,
+    };
+
+    let exclude = match matches.values_of("exclude").unwrap_or_default().map(Pattern::new).collect() {
+        Ok(exclude) => exclude,
+        Err(e) => {
+            eprintln!("Invalid value for --exclude: {}", e);
+            return ExitStatus::Usage;
+        }
+    };
+
+    let psk_iterations = if matches.is_present("psk-iterations") {
+        match value_t!(matches, "psk-iterations", u32) {
+            Ok(iterations) if iterations > 0 => iterations,
+            _ => {
+                eprintln!("Invalid value for --psk-iterations, expected a positive integer");
+                return ExitStatus::Usage;
+            }
+        }
+    } else {
+        PBKDF2_ITERATIONS
+    };
+
+    if psk_iterations != PBKDF2_ITERATIONS {
+        eprintln!("WARNING: using {} PBKDF2 iterations instead of the standard {}. The generated PSKs \
+                   will not work with standard WPA2 networks.", psk_iterations, PBKDF2_ITERATIONS);
+    }
+
+    let jobs = if matches.is_present("jobs") {
+        match value_t!(matches, "jobs", usize) {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => {
+                eprintln!("Invalid value for --jobs, expected a positive integer");
+                return ExitStatus::Usage;
+            }
+        }
+    } else {
+        0
+    };
+
+    let mode = match matches.value_of("mode").map(|mode| u32::from_str_radix(mode, 8)) {
+        Some(Ok(mode)) if mode <= 0o7777 => Some(mode),
+        Some(_) => {
+            eprintln!("Invalid value for --mode, expected octal permissions like 0640");
+            return ExitStatus::Usage;
+        }
+        None => None,
+    };
+
+    let owner = match matches.value_of("owner").map(str::parse::<Owner>).transpose() {
+        Ok(owner) => owner,
+        Err(e) => {
+            eprintln!("Invalid value for --owner: {}", e);
+            return ExitStatus::Usage;
+        }
+    };
+
+    let cert_dir = if matches.is_present("copy-certs") {
+        if output == STREAM_OUTPUT {
+            eprintln!("--copy-certs needs an output directory to refer to");
+            return ExitStatus::Usage;
+        }
+
+        match std::path::absolute(output) {
+            Ok(dir) => Some(dir),
+            Err(e) => {
+                eprintln!("Invalid output directory {}: {}", output, e);
+                return ExitStatus::Usage;
+            }
+        }
+    } else {
+        None
+    };
+
+    let log = match matches.value_of("log-file").map(|path| Log::open(Path::new(path))).transpose() {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Failed to open the log file {}: {}", matches.value_of("log-file").unwrap_or_default(), e);
+            return ExitStatus::Usage;
+        }
+    };
+
+    let options = Options {
+        parse: ParseOptions {
+            strict: matches.is_present("strict"),
+            expand_env_secrets: matches.is_present("expand-env-secrets"),
+            secrets,
+            expand_security: matches.is_present("expand-security"),
+            assume_security: matches.value_of("assume-security").map(str::to_owned),
+        },
+        write: WriteOptions {
+            hex_case: optional_value(matches, "hex-case"),
+            psk_iterations,
+            precompute_psk: !matches.is_present("no-precompute"),
+            passthrough: matches.is_present("passthrough"),
+            format: optional_value(matches, "output-format"),
+        },
+        stamp: matches.is_present("stamp"),
+        base_settings,
+        layout: optional_value(matches, "iwd-layout"),
+        split_by_security: matches.is_present("split-by-security"),
+        sort: optional_value(matches, "sort-output"),
+        count: matches.is_present("count"),
+        annotate_open: matches.is_present("annotate-open"),
+        dry_run: matches.is_present("dry-run"),
+        force: matches.is_present("force"),
+        interactive: matches.is_present("interactive"),
+        backup: matches.is_present("backup"),
+        stdout: matches.is_present("stdout"),
+        recursive: matches.is_present("recursive"),
+        quiet: matches.is_present("quiet"),
+        progress: matches.is_present("progress"),
+        verbose: matches.occurrences_of("verbose").min(2) as u8,
+        jobs,
+        cert_dir,
+        mode,
+        owner,
+        interface: matches.value_of("interface").filter(|_| !matches.is_present("reverse")).map(str::to_owned),
+        output_name: matches.value_of("output-name").map(str::to_owned),
+        output_suffix: matches.value_of("output-suffix").map(str::to_owned),
+        exclude,
+        preserve_times: matches.is_present("preserve-times"),
+        merge: matches.is_present("merge"),
+        remap,
+        allow_downgrade: matches.is_present("allow-downgrade"),
+        only_security: matches.value_of("only-security").map(str::to_owned),
+        dedupe: dirs.len() > 1,
+        log,
+        // Without a default value, so it only conflicts with other arguments when given.
+        format: optional_value(matches, "format"),
+    };
+
+    let color = match matches.value_of("color") {
+        Some("always") => Color::Always,
+        Some("never") => Color::Never,
+        _ => Color::Auto,
+    };
+
+    if options.output_name.is_some() && !matches.is_present("input") && !matches.is_present("profile-name") {
+        eprintln!("--output-name needs exactly one input profile");
+        return ExitStatus::Usage;
+    }
+
+    if options.output_suffix.as_deref().is_some_and(|suffix| suffix.contains('/')) {
+        eprintln!("--output-suffix can't contain a /");
+        return ExitStatus::Usage;
+    }
+
+    if matches.is_present("reverse") {
+        let inputs = matches.values_of("input").unwrap_or_default();
+        return reverse(inputs, matches.value_of("interface").unwrap_or("wlan0"));
+    }
+
+    // Modes that only read the profiles.
+    let listing = command == "list";
+    let checking = command == "check";
+    let writes_files = output != STREAM_OUTPUT && !options.count && !listing && !checking && !options.dry_run && !options.stdout;
+    if writes_files {
+        if let Err(e) = ensure_output_dir(Path::new(output), matches.is_present("mkdir")) {
+            eprintln!("{}", e);
+            // A missing directory is a matter of passing --mkdir, rather than a failure to create it.
+            return match e {
+                ConversionError::MissingOutputDir(_) => ExitStatus::Usage,
+                _ => ExitStatus::Failures,
+            };
+        }
+        // Fail once, rather than for every profile.
+        if let Err(e) = probe_output_dir(Path::new(output)) {
+            eprintln!("Cannot write to output directory {}: {}", output, e);
+            return ExitStatus::Failures;
+        }
+    }
+
+    // Held until the end of the run.
+    let _lock = if matches.is_present("no-lock") || !writes_files {
+        None
+    } else {
+        match lock_output(Path::new(output)) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("Failed to lock output directory {}: {}", output, e);
+                return ExitStatus::Failures;
+            }
+        }
+    };
+
+    if matches.is_present("unpack") {
+        return unpack(output);
+    }
+
+    // Profiles that couldn't even be found, which count as failures.
+    let mut unresolved = Vec::new();
+    // Profiles in the scanned directory that match --exclude.
+    let mut excluded = 0;
+
+    let archive = matches.value_of("tar");
+
+    let files = if archive.is_some() {
+        // Read by `convert_tar` instead.
+        Vec::new()
+    } else if !dirs.is_empty() {
+        let mut files = Vec::new();
+
+        for dir in &dirs {
+            match list_dir(dir, options.recursive) {
+                Ok(mut listed) => {
+                    excluded += exclude_profiles(&mut listed, &options.exclude);
+                    files.extend(listed);
+                }
+                Err(e) => {
+                    eprintln!("Failed to open {} for reading: {}", dir, e);
+                    return ExitStatus::InputUnreadable;
+                }
+            }
+        }
+
+        files
+    } else if let Some(inputs) = matches.values_of("input") {
+        let mut files = Vec::new();
+
+        for input in inputs {
+            let input = match expand_path(input) {
+                Ok(input) => input,
+                Err(e) => {
+                    eprintln!("Invalid path: {}", e);
+                    return ExitStatus::Usage;
+                }
+            };
+
+            match expand_input(&input) {
+                Ok(expanded) => files.extend(expanded),
+                Err(error) => {
+                    eprintln!("{}", color.paint(RED, format!("Failed to convert {}: {}", input, describe(&error)), Stream::Stderr));
+                    if let Some(log) = &options.log {
+                        log.entry(&input, format_args!("failed: {}", error));
+                    }
+                    unresolved.push((input, Err(error)));
+                }
+            }
+        }
+
+        files
+    } else if let Some(names) = matches.values_of("profile-name") {
+        let netctl_dir = Path::new(&netctl_dir);
+        let mut files = Vec::new();
+
+        for name in names {
+            match resolve_profile(netctl_dir, name) {
+                Ok(path) => files.push(path.display().to_string()),
+                Err(error) => {
+                    eprintln!("{}", color.paint(RED, format!("Failed to convert {}: {}", name, describe(&error)), Stream::Stderr));
+                    if let Some(log) = &options.log {
+                        log.entry(name, format_args!("failed: {}", error));
+                    }
+                    unresolved.push((name.to_owned(), Err(error)));
+                }
+            }
+        }
+
+        files
+    } else {
+        // Nothing specified, so migrate everything in the netctl directory.
+        let dir = &netctl_dir;
+        if !options.count && !listing && !checking && !options.dry_run && !matches.is_present("yes") && !confirm(&format!("Convert all profiles in {} to {}?", dir, output)) {
+            return ExitStatus::NothingToDo;
+        }
+
+        eprintln!("Scanning {} for netctl profiles", dir);
+        match list_dir(dir, options.recursive) {
+            Ok(mut files) => {
+                excluded = exclude_profiles(&mut files, &options.exclude);
+                files
+            }
+            Err(e) => {
+                eprintln!("Failed to open {} for reading: {}", dir, e);
+                return ExitStatus::InputUnreadable;
+            }
+        }
+    };
+
+    if options.output_name.is_some() && files.len() + unresolved.len() != 1 {
+        eprintln!("--output-name needs exactly one input profile, got {}", files.len() + unresolved.len());
+        return ExitStatus::Usage;
+    }
+
+    if checking {
+        match check(&check_files(files.iter(), &options), color) {
+            ExitStatus::Success if unresolved.is_empty() => return ExitStatus::Success,
+            _ => return ExitStatus::Failures,
+        }
+    }
+
+    if listing {
+        match list(&list_files(files.iter(), &options), options.write.hex_case) {
+            ExitStatus::Success if unresolved.is_empty() => return ExitStatus::Success,
+            _ => return ExitStatus::Failures,
+        }
+    }
+
+    if options.count {
+        let counts = match archive {
+            Some(archive) => match count_tar(archive, &options) {
+                Ok(counts) => counts,
+                Err(e) => {
+                    eprintln!("Failed to open {} for reading: {}", archive, e);
+                    return ExitStatus::InputUnreadable;
+                }
+            },
+            None => count_files(files.iter(), &options),
+        };
+        println!("Convertible: {}\nSkipped: {}\nErrors: {}", counts.convertible, counts.skipped, counts.errors);
+        return ExitStatus::Success;
+    }
+
+    let streaming = output == STREAM_OUTPUT;
+    let results = match archive {
+        Some(archive) => match convert_tar(archive, output, &options) {
+            Ok(results) => results,
+            Err(e) => {
+                eprintln!("Failed to open {} for reading: {}", archive, e);
+                return ExitStatus::InputUnreadable;
+            }
+        },
+        None => convert_files(files.iter(), output, &options),
+    };
+    report(&results, &options, streaming, color);
+
+    unresolved.extend(results);
+    let summary = Summary { excluded, ..Summary::from(&unresolved[..]) };
+
+    if let Some(path) = matches.value_of("failures-out") {
+        if let Err(e) = File::create(path).and_then(|mut file| write_failures(&mut file, &unresolved)) {
+            eprintln!("Failed to write the failures to {}: {}", path, e);
+            return ExitStatus::Failures;
+        }
+    }
+
+    let report = match options.format {
+        OutputFormat::Text => summary.to_string(),
+        OutputFormat::Json => serde_json::to_string_pretty(&summary.records).expect("records always serialize"),
+    };
+
+    // Keep stdout clean for the generated files.
+    if streaming || options.stdout {
+        eprintln!("{}", report);
+    } else {
+        println!("{}", report);
+    }
+
+    #[cfg(feature = "dbus")]
+    {
+        use netctl2iwd::reload::{ReloadMode, Reloaded};
+
+        if matches.is_present("reload-iwd") && summary.converted > 0 {
+            let mode = match matches.value_of("reload-iwd") {
+                Some("restart") => ReloadMode::Restart,
+                _ => ReloadMode::Check,
+            };
+            let mut ssids: Vec<String> = summary.records.iter()
+                .filter(|record| record.success)
+                .filter_map(|record| record.ssid.clone())
+                .collect();
+            ssids.sort();
+            ssids.dedup();
+
+            match netctl2iwd::reload::reload_iwd(&ssids, mode) {
+                Ok(Reloaded::Known) => println!("iwd has loaded the new networks"),
+                Ok(Reloaded::Missing(missing)) => println!(
+                    "iwd hasn't loaded {}; --reload-iwd=restart restarts it, which drops its connections",
+                    missing.join(", "),
+                ),
+                Ok(Reloaded::Restarted) => println!("Restarted iwd"),
+                Err(e @ netctl2iwd::reload::ReloadError::NotRunning) => println!("{}", e),
+                Err(e) => {
+                    eprintln!("Failed to reload iwd: {}", e);
+                    return ExitStatus::Failures;
+                }
+            }
+        }
+    }
+
+    ExitStatus::from(&summary)
+}
+
+/// Arguments selecting and parsing the profiles, which every subcommand takes.
+fn input_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("input")
             .conflicts_with("dir")
-            .required(true)
-            .help("Profile files to process")
-            .multiple(true))
-        .arg(Arg::with_name("output")
-            .short("o")
-            .long("output-dir")
-            .default_value(DEFAULT_INSTALL_PATH))
-        .arg(Arg::with_name("dir")
-            .help("Directory of profiles to process")
+            .help("Profile files or wildcard patterns to process, or - to read a single profile from stdin (not with --input-dir)")
+            .multiple(true),
+        Arg::with_name("dir")
+            .help("Directory of profiles to process; if given more than once, only the first profile for each network is converted [default: the --netctl-dir, if no input is given]")
             .conflicts_with("input")
             .long("input-dir")
             .short("i")
-            .required(true)
-            .takes_value(true))
-        .get_matches();
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("tar")
+            .help("Tar archive of profiles to process, which may be gzipped, without extracting it")
+            .long("input-tar")
+            .takes_value(true)
+            .conflicts_with_all(&["input", "dir", "profile-name", "unpack", "reverse"]),
+        Arg::with_name("profile-name")
+            .help("Name of a netctl profile to process, as found in the netctl directory")
+            .long("profile-name")
+            .short("p")
+            .conflicts_with_all(&["input", "dir", "unpack"])
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("exclude")
+            .help("Skip profiles in the scanned directory whose file names match this pattern, like '*.bak'")
+            .long("exclude")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("only-security")
+            .help("Only convert the networks of this security type, for migrating one kind at a time")
+            .long("only-security")
+            .possible_values(&["open", "psk", "8021x"])
+            .takes_value(true),
+        Arg::with_name("netctl-dir")
+            .help("Directory in which netctl profiles are looked up by name, and scanned if no input is given")
+            .long("netctl-dir")
+            .default_value(DEFAULT_NETCTL_PATH),
+        Arg::with_name("yes")
+            .help("Don't ask for confirmation before scanning the netctl directory")
+            .long("yes")
+            .short("y"),
+        Arg::with_name("recursive")
+            .help("Also convert profiles in subdirectories of the input directory")
+            .long("recursive")
+            .short("r"),
+        Arg::with_name("interface")
+            .help("Only convert profiles for this interface; with --reverse, the interface for the written profiles [default: wlan0]")
+            .long("interface")
+            .takes_value(true),
+        Arg::with_name("strict")
+            .help("Refuse to convert profiles that produce warnings")
+            .long("strict"),
+        Arg::with_name("expand-env-secrets")
+            .help("Substitute keys like ${NAME} with the value of that environment variable")
+            .long("expand-env-secrets"),
+        Arg::with_name("expand-security")
+            .help("Convert each Security type of a profile listing several into its own file, instead of only the strongest")
+            .long("expand-security"),
+        Arg::with_name("assume-security")
+            .help("Security type of profiles that have a Key but no Security, which are refused otherwise")
+            .long("assume-security")
+            .possible_values(&["wpa", "sae"])
+            .takes_value(true),
+        Arg::with_name("allow-downgrade")
+            .help("Convert networks whose iwd files would be less strict about security than their profiles, like WPA3-only ones")
+            .long("allow-downgrade"),
+        Arg::with_name("secrets")
+            .help("ini file of SSID = passphrase lines, completing profiles whose Key is missing or empty")
+            .long("secrets")
+            .takes_value(true),
+        Arg::with_name("remap")
+            .help("ini file of old SSID = new SSID lines, converting those networks under their new names")
+            .long("remap")
+            .takes_value(true),
+        Arg::with_name("jobs")
+            .help("Maximum number of profiles to convert at the same time [default: one per CPU]")
+            .long("jobs")
+            .short("j")
+            .takes_value(true),
+        Arg::with_name("progress")
+            .help("Show how many profiles have been processed, if stderr is a terminal")
+            .long("progress"),
+        Arg::with_name("color")
+            .help("Colorize the reported results: auto only does so on a terminal, unless NO_COLOR is set")
+            .long("color")
+            .possible_values(&["auto", "always", "never"])
+            .default_value("auto"),
+    ]
+}
+
+/// Arguments for converting the profiles and writing the results.
+fn convert_args() -> Vec<Arg<'static, 'static>> {
+    #[allow(unused_mut)]
+    let mut args = vec![
+        Arg::with_name("output")
+            .help("Directory to write iwd files to, or - to stream them to stdout")
+            .short("o")
+            .long("output-dir")
+            .default_value(DEFAULT_INSTALL_PATH),
+        Arg::with_name("format")
+            .help("Report the results as text, or as a JSON array with a record per generated file or failed profile")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .conflicts_with_all(&["unpack", "count", "dry-run"]),
+        Arg::with_name("quiet")
+            .help("Only report profiles that were not converted, on stderr, and the summary")
+            .long("quiet")
+            .short("q"),
+        Arg::with_name("verbose")
+            .help("Explain how profiles are converted; use twice to include the parsed networks")
+            .long("verbose")
+            .short("v")
+            .multiple(true)
+            .conflicts_with("quiet"),
+        Arg::with_name("unpack")
+            .help("Read a stream produced by --output-dir - from stdin and write its files")
+            .long("unpack")
+            .conflicts_with_all(&["input", "dir"]),
+        Arg::with_name("reverse")
+            .help("Convert the given iwd files back to netctl profiles, printed to stdout")
+            .long("reverse")
+            .requires("input")
+            .conflicts_with_all(&["unpack", "dir"]),
+        Arg::with_name("sort-output")
+            .help("Order of files when streaming to stdout")
+            .long("sort-output")
+            .possible_values(&["name", "ssid", "security", "source"])
+            .default_value("name"),
+        hex_case_arg(),
+        Arg::with_name("psk-iterations")
+            .help("PBKDF2 iterations for precomputed PSKs; anything but 4096 breaks standard WPA2")
+            .long("psk-iterations")
+            .takes_value(true),
+        Arg::with_name("no-precompute")
+            .help("Only write the passphrase of PSK networks, and let iwd derive the PSK itself")
+            .long("no-precompute")
+            .conflicts_with("psk-iterations"),
+        Arg::with_name("output-format")
+            .help("Keys to write: everything, everything but precomputed PSKs, or only the secrets and whether the network is hidden")
+            .long("output-format")
+            .possible_values(&["default", "passphrase-only", "minimal"])
+            .default_value("default"),
+        Arg::with_name("passthrough")
+            .help("Translate the remaining options of a WPAConfigSection where possible, and keep the others as comments")
+            .long("passthrough"),
+        Arg::with_name("iwd-layout")
+            .help("Storage layout: classic single files, or split with secrets in a separate directory")
+            .long("iwd-layout")
+            .possible_values(&["classic", "split"])
+            .default_value("classic"),
+        Arg::with_name("split-by-security")
+            .help("Write the files for each security type to their own subdirectory, like open/ and psk/")
+            .long("split-by-security"),
+        Arg::with_name("stamp")
+            .help("Start each file with a comment noting when and by what it was generated, and from which profile")
+            .long("stamp"),
+        Arg::with_name("dry-run")
+            .help("Print the files that would be written instead of writing them")
+            .long("dry-run")
+            .short("n")
+            .conflicts_with("unpack"),
+        Arg::with_name("stdout")
+            .help("Write the iwd configurations to stdout instead of the output directory")
+            .long("stdout")
+            .conflicts_with_all(&["unpack", "dry-run"]),
+        Arg::with_name("force")
+            .help("Overwrite existing iwd files")
+            .long("force")
+            .short("f"),
+        Arg::with_name("interactive")
+            .help("Ask before overwriting each existing iwd file, if stdin is a terminal")
+            .long("interactive")
+            .short("I"),
+        Arg::with_name("output-name")
+            .help("Name of the iwd file to write for a single profile, instead of the one derived from its SSID; the extension is added if missing")
+            .long("output-name")
+            .takes_value(true)
+            .conflicts_with_all(&["dir", "tar", "list", "reverse"]),
+        Arg::with_name("output-suffix")
+            .help("Append this to the names of the iwd files, like .new, so iwd ignores them until they're renamed")
+            .long("output-suffix")
+            .takes_value(true)
+            .conflicts_with_all(&["unpack", "reverse"]),
+        Arg::with_name("backup")
+            .help("Rename files replaced by --force or --interactive to <name>.bak instead of overwriting them")
+            .long("backup")
+            .requires("overwrite"),
+        Arg::with_name("mode")
+            .help("Permissions of the written files, in octal, except for private keys and secrets, which stay 0600 [default: 0600]")
+            .long("mode")
+            .takes_value(true),
+        Arg::with_name("owner")
+            .help("Owner of the written files, as user, user:group or :group")
+            .long("owner")
+            .takes_value(true),
+        Arg::with_name("copy-certs")
+            .help("Copy the certificates and keys of enterprise networks to the output directory, and refer to the copies")
+            .long("copy-certs"),
+        Arg::with_name("annotate-open")
+            .help("Add an explanatory comment to files for open networks, which are empty otherwise")
+            .long("annotate-open"),
+        Arg::with_name("merge")
+            .help("Merge the generated settings into existing iwd files, keeping the sections and keys only found there")
+            .long("merge")
+            .conflicts_with_all(&["unpack", "stdout", "dry-run"]),
+        Arg::with_name("preserve-times")
+            .help("Give each written file the modification and access times of its profile")
+            .long("preserve-times"),
+        Arg::with_name("mkdir")
+            .help("Create the output directory and its parents if they don't exist")
+            .long("mkdir"),
+        Arg::with_name("no-lock")
+            .help("Don't lock the output directory against concurrent runs")
+            .long("no-lock"),
+        Arg::with_name("failures-out")
+            .help("Write the profiles that failed to convert to this file, a line each with the profile and the error separated by a tab")
+            .long("failures-out")
+            .takes_value(true)
+            .conflicts_with_all(&["unpack", "count"]),
+        Arg::with_name("log-file")
+            .help("Append a timestamped line to this file for each step of converting each profile, like the files written or why it failed")
+            .long("log-file")
+            .takes_value(true)
+            .conflicts_with_all(&["unpack", "count"]),
+        Arg::with_name("count")
+            .help("Only print how many profiles would convert, be skipped or fail, without writing anything")
+            .long("count")
+            .conflicts_with("unpack"),
+        Arg::with_name("base-settings")
+            .help("iwd file with settings to include in every generated file")
+            .long("base-settings")
+            .takes_value(true),
+        Arg::with_name("base-settings-open")
+            .help("iwd file with settings to include in generated open network files")
+            .long("base-settings-open")
+            .takes_value(true),
+        Arg::with_name("base-settings-psk")
+            .help("iwd file with settings to include in generated PSK network files")
+            .long("base-settings-psk")
+            .takes_value(true),
+        Arg::with_name("base-settings-8021x")
+            .help("iwd file with settings to include in generated enterprise network files")
+            .long("base-settings-8021x")
+            .takes_value(true),
+    ];
+
+    #[cfg(feature = "dbus")]
+    args.push(Arg::with_name("reload-iwd")
+        .help("Check that iwd has loaded the new networks; with =restart, restart iwd if it hasn't, which drops all its connections")
+        .long("reload-iwd")
+        .takes_value(true)
+        .min_values(0)
+        .require_equals(true)
+        .possible_values(&["check", "restart"]));
+
+    args
+}
+
+/// At most one way to decide about existing files.
+fn overwrite_group() -> ArgGroup<'static> {
+    ArgGroup::with_name("overwrite")
+        .args(&["force", "interactive"])
+}
+
+/// The modes that predate the subcommands, kept for existing invocations.
+fn mode_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("check")
+            .help("Only report the problems converting the profiles would run into, without writing anything")
+            .long("check")
+            .conflicts_with_all(&["unpack", "reverse", "count", "list", "tar", "format"]),
+        Arg::with_name("list")
+            .help("Only print a table of the networks in the profiles and the files they would be written to")
+            .long("list")
+            .conflicts_with_all(&["unpack", "reverse", "count", "tar", "format"]),
+    ]
+}
+
+/// Shared by `convert` and `list`, which both name the iwd files.
+fn hex_case_arg() -> Arg<'static, 'static> {
+    Arg::with_name("hex-case")
+        .help("Letter case for hex-encoded file names and keys")
+        .long("hex-case")
+        .possible_values(&["lower", "upper"])
+        .default_value("lower")
+}
+
+/// The value of an argument that not every subcommand has, or its default.
+fn optional_value<T: FromStr + Default>(matches: &ArgMatches, name: &str) -> T {
+    if matches.is_present(name) {
+        value_t_or_exit!(matches, name, T)
+    } else {
+        T::default()
+    }
+}
+
+/// Print what happened to each converted profile.
+fn report(results: &[Conversion], options: &Options, streaming: bool, color: Color) {
+    let text = options.format == OutputFormat::Text;
+    // Keep stdout clean for the stream itself, or for the summary when only problems are reported.
+    let stream = if streaming || options.stdout || options.quiet { Stream::Stderr } else { Stream::Stdout };
+
+    for (source, result) in results {
+        let message = match result {
+            Ok(converted) => {
+                for warning in &converted.warnings {
+                    eprintln!("Warning: {}: {}", source, warning);
+                }
+                for detail in &converted.details {
+                    eprintln!("Debug: {}: {}", source, detail);
+                }
+                if text {
+                    for (path, backup) in &converted.backups {
+                        println!("Backed up {} to {}", path.display(), backup.display());
+                    }
+                }
+
+                if options.quiet {
+                    continue;
+                } else if options.dry_run && !streaming {
+                    format!("Would convert {}", source)
+                } else {
+                    match converted.outcome {
+                        Outcome::Unchanged => format!("Unchanged {}", source),
+                        Outcome::Written => color.paint(GREEN, format!("Successfully converted {}", source), stream),
+                        Outcome::Overwritten => color.paint(GREEN, format!("Successfully converted {}, overwriting the existing file", source), stream),
+                    }
+                }
+            }
+            Err(error @ ConversionError::FileExists(_)) => format!("Skipped {}: {}", source, error),
+            Err(error @ ConversionError::OtherSecurity(_)) => format!("Skipped {}: {}", source, error),
+            Err(error) => color.paint(RED, format!("Failed to convert {}: {}", source, describe(error)), stream),
+        };
+
+        if !text {
+            continue;
+        }
+
+        match stream {
+            Stream::Stderr => eprintln!("{}", message),
+            Stream::Stdout => println!("{}", message),
+        }
+    }
+}
+
+/// Ask the user a yes/no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
 
-    let output = matches.value_of("output").unwrap();
+/// Load the baseline settings files given on the command line.
+fn base_settings(matches: &ArgMatches) -> Result<BaseSettings, ExitStatus> {
+    let mut settings = BaseSettings {
+        all: matches.value_of("base-settings").map(load_settings).transpose()?,
+        ..BaseSettings::default()
+    };
 
-    if let Some(dir) = matches.value_of("dir") {
-        convert_dir(dir, output);
-    } else if let Some(files) = matches.values_of("input") {
-        convert_files(files, output);
+    for security in &["open", "psk", "8021x"] {
+        if let Some(path) = matches.value_of(format!("base-settings-{}", security)) {
+            settings.per_security.insert(security.to_string(), load_settings(path)?);
+        }
+    }
+
+    Ok(settings)
+}
+
+fn load_settings(path: &str) -> Result<Ini, ExitStatus> {
+    Ini::load_from_file(path).map_err(|e| {
+        eprintln!("Failed to read base settings {}: {}", path, e);
+        ExitStatus::Usage
+    })
+}
+
+/// Read a file of `key = value` lines, like passphrases by SSID.
+///
+/// Values are taken literally, without the quotes, escapes and comments of ini files, since
+/// passphrases can contain any of those. Empty lines and lines starting with `#` or `;` are skipped.
+fn load_map(path: &str, what: &str) -> Result<HashMap<String, String>, ExitStatus> {
+    let fail = |e: &dyn std::fmt::Display| {
+        eprintln!("Failed to read {} {}: {}", what, path, e);
+        ExitStatus::Usage
+    };
+    let contents = fs::read_to_string(path).map_err(|e| fail(&e))?;
+
+    parse_map(&contents).map_err(|e| fail(&e))
+}
+
+/// Parse the `key = value` lines of `load_map`, splitting each at its first `=`.
+fn parse_map(contents: &str) -> Result<HashMap<String, String>, String> {
+    let mut map = HashMap::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => map.insert(key.trim().to_owned(), value.trim().to_owned()),
+            _ => return Err(format!("line {}: expected key = value", number + 1)),
+        };
+    }
+
+    Ok(map)
+}
+
+/// An error message followed by its underlying cause, like the `io::Error` that an
+/// `OSError` only calls unknown.
+fn describe(error: &ConversionError) -> String {
+    match error.source().map(|source| source.to_string()) {
+        Some(cause) if !error.to_string().contains(&cause) => format!("{}: {}", error, cause),
+        _ => error.to_string(),
+    }
+}
+
+/// Print the problems found with each profile, and a line for each profile without any.
+fn check(checks: &[Check], color: Color) -> ExitStatus {
+    let mut problems = 0;
+
+    for (source, result) in checks {
+        match result {
+            Ok(warnings) if warnings.is_empty() => println!("{}: ok", source),
+            Ok(warnings) => {
+                for warning in warnings {
+                    println!("{}", color.paint(RED, format!("{}: warning: {}", source, warning), Stream::Stdout));
+                }
+                problems += warnings.len();
+            }
+            Err(error) => {
+                println!("{}", color.paint(RED, format!("{}: error: {}", source, describe(error)), Stream::Stdout));
+                problems += 1;
+            }
+        }
+    }
+
+    println!("{} problems in {} profiles", problems, checks.len());
+    if problems == 0 {
+        ExitStatus::Success
     } else {
-        unreachable!("clap should handle this");
+        ExitStatus::Failures
+    }
+}
+
+/// Print the networks of each profile as a table, with the profiles that failed to parse in between.
+fn list(listings: &[Listing], hex_case: HexCase) -> ExitStatus {
+    let mut rows = vec![Ok(["PROFILE", "INTERFACE", "SSID", "SECURITY", "FILE"].map(str::to_owned))];
+    for (source, result) in listings {
+        match result {
+            Ok(profile) => rows.extend(profile.networks.iter().map(|network| Ok([
+                source.clone(),
+                profile.interface.clone().unwrap_or_else(|| "-".to_owned()),
+                network.ssid().to_string(),
+                network.security().type_name().to_owned(),
+                network.iwd_file_name(hex_case),
+            ]))),
+            Err(error) => rows.push(Err([source.clone(), format!("error: {}", describe(error))])),
+        }
+    }
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        let cells = match row {
+            Ok(cells) => &cells[..4],
+            Err(cells) => &cells[..1],
+        };
+        for (width, cell) in widths.iter_mut().zip(cells) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in &rows {
+        let cells = match row {
+            Ok(cells) => &cells[..],
+            Err(cells) => &cells[..],
+        };
+        let line: Vec<_> = cells.iter().enumerate()
+            .map(|(i, cell)| match widths.get(i) {
+                Some(width) if i + 1 < cells.len() => format!("{:w$}", cell, w = width),
+                _ => cell.clone(),
+            })
+            .collect();
+        println!("{}", line.join("  "));
+    }
+
+    if listings.iter().all(|(_, result)| result.is_ok()) {
+        ExitStatus::Success
+    } else {
+        ExitStatus::Failures
+    }
+}
+
+/// Print a netctl profile for each iwd file, each preceded by a comment naming its source.
+fn reverse<'a>(inputs: impl Iterator<Item=&'a str>, interface: &str) -> ExitStatus {
+    let mut status = ExitStatus::Success;
+
+    for input in inputs {
+        match iwd::read_network(Path::new(input)).and_then(|network| write_profile(&network, interface)) {
+            Ok(profile) => print!("# netctl profile from {}\n{}", input, profile),
+            Err(e) => {
+                eprintln!("Failed to convert {}: {}", input, e);
+                status = ExitStatus::Failures;
+            }
+        }
+    }
+
+    status
+}
+
+fn unpack(output: &str) -> ExitStatus {
+    let stdin = io::stdin();
+    match stream::unpack(&mut stdin.lock(), Path::new(output)) {
+        Ok(written) if written.is_empty() => ExitStatus::NothingToDo,
+        Ok(written) => {
+            for path in written {
+                println!("Wrote {}", path.display());
+            }
+            ExitStatus::Success
+        }
+        Err(e) => {
+            eprintln!("Failed to unpack stream: {}", e);
+            ExitStatus::Failures
+        }
     }
 }
@@ -0,0 +1,1802 @@
+//! Parsing of netctl profiles, independent of any output format.
+//!
+//! Profiles are parsed into `Network`s, which the writers for the output formats consume.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::io::ErrorKind;
+use std::io::Read;
+
+use ini::Ini;
+
+use crate::convert::ConversionError;
+use crate::ip::prefix_from_netmask;
+use crate::ip::Address;
+use crate::ip::IpConfig;
+use crate::ip::StaticConfig;
+use crate::networks::compute_psk;
+use crate::networks::Band;
+use crate::networks::EAPMethod;
+use crate::networks::EAPSecurity;
+use crate::networks::MacAddress;
+use crate::networks::Network;
+use crate::networks::PSKSecurity;
+use crate::networks::Security;
+use crate::networks::Ssid;
+
+/// Problems in a profile that don't prevent conversion, but may produce unexpected results.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Warning {
+    /// The value of the key looks like it relies on shell expansion, which is not performed.
+    ShellExpansion(String),
+    /// The profile has a non-default `Priority`, which iwd has no equivalent for.
+    UnsupportedPriority(i32),
+    /// Keys of the profile that are not converted, in alphabetical order.
+    IgnoredKeys(Vec<String>),
+    /// The profile only allows frequencies in one band, which iwd can't restrict networks to.
+    UnsupportedBand(Band),
+    /// The profile converts to the same file as an earlier one, with different contents.
+    Collision(String, String),
+    /// The precomputed PSK of the profile isn't derived from its passphrase and this SSID.
+    MismatchedPSK(String),
+    /// The profile is disabled by commenting out `Connection=wireless`.
+    DisabledProfile,
+    /// The profile lists several `Security` types, of which only the first ones are converted.
+    IgnoredSecurities(Vec<String>, Vec<String>),
+    /// The network is converted with less strict security, for the reasons given.
+    Downgrade(String, Vec<String>),
+    /// The network was already converted from an earlier profile, so it is left out.
+    Duplicate(String, String),
+    /// The `key_mgmt` of a `Security=wpa` profile allows both personal and enterprise
+    /// authentication, and the network is converted as the given security type.
+    AmbiguousKeyMgmt(String, String),
+    /// Keys of an existing iwd file that were replaced by merging the generated configuration
+    /// into it.
+    MergedKeys(String, Vec<String>),
+    /// DNS domains that aren't converted, because iwd only has a domain name for static IPv4.
+    IgnoredDomains(Vec<String>),
+    /// The profile has a `Key` but no `Security`, so it is converted as this type.
+    AssumedSecurity(String),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Warning::ShellExpansion(key) => write!(f, "{} appears to use shell expansion, which is not evaluated", key),
+            Warning::IgnoredKeys(keys) => write!(f, "Ignored keys: {}", keys.join(", ")),
+            Warning::Collision(name, other) => write!(f, "{} also converts to {}, so only one of them can be written", other, name),
+            Warning::UnsupportedBand(band) => write!(f, "The profile only uses {} frequencies, but iwd has no per-network band setting; consider the [Rank] band modifiers in iwd's main.conf", band),
+            Warning::UnsupportedPriority(priority) => write!(f, "Priority={} is ignored, because iwd orders networks by when they were last connected", priority),
+            Warning::DisabledProfile => write!(f, "Connection=wireless is commented out, so the network is converted with AutoConnect=false"),
+            Warning::AmbiguousKeyMgmt(key_mgmt, used) => write!(f, "key_mgmt={} allows both personal and enterprise authentication; converted as {}", key_mgmt, used),
+            Warning::AssumedSecurity(kind) => write!(f, "The profile has a Key but no Security, so it is converted as Security={}", kind),
+            Warning::IgnoredDomains(domains) => write!(f, "iwd only has a single DNS domain name, for static IPv4 configurations; ignored {}", domains.join(", ")),
+            Warning::MergedKeys(name, keys) => write!(f, "Merging into the existing {} replaced {}", name, keys.join(", ")),
+            Warning::Duplicate(ssid, first) => write!(f, "{} is left out, since it was already converted from {}", ssid, first),
+            Warning::Downgrade(ssid, reasons) => write!(f, "{} is converted with weaker security, since {}", ssid, reasons.join(", ")),
+            Warning::IgnoredSecurities(used, ignored) => write!(f, "The profile lists several security types; converted {} and ignored {}", used.join(", "), ignored.join(", ")),
+            Warning::MismatchedPSK(ssid) => write!(f, "The precomputed psk does not match the passphrase for {}; was the SSID or passphrase changed after computing it?", ssid),
+        }
+    }
+}
+
+/// Ways in which the shell quoting of a value can be malformed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum QuotingError {
+    /// A quote is opened but never closed.
+    UnterminatedQuote,
+    /// A backslash at the end of the value, escaping nothing.
+    UnterminatedEscape,
+}
+
+impl Display for QuotingError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            QuotingError::UnterminatedQuote => write!(f, "unterminated quote"),
+            QuotingError::UnterminatedEscape => write!(f, "unterminated escape sequence"),
+        }
+    }
+}
+
+/// Options controlling how profiles are parsed.
+#[derive(Default, Clone, Debug)]
+pub struct ParseOptions {
+    /// Turn warnings into errors.
+    pub strict: bool,
+    /// Substitute keys of the form `${NAME}` with the value of that environment variable.
+    pub expand_env_secrets: bool,
+    /// Passphrases by SSID, for profiles that leave their `Key` out or empty.
+    pub secrets: HashMap<String, String>,
+    /// Convert each of several `Security` types into its own file, instead of only the strongest.
+    pub expand_security: bool,
+    /// The `Security` type of profiles that have a `Key` but leave `Security` out, which are
+    /// refused otherwise.
+    pub assume_security: Option<String>,
+}
+
+/// A parsed profile.
+#[derive(Debug)]
+pub struct Profile {
+    /// The networks described by the profile, never empty.
+    pub networks: Vec<Network>,
+    pub warnings: Vec<Warning>,
+    /// Keys the parser interpreted, in alphabetical order.
+    pub keys: Vec<String>,
+    /// Keys the parser doesn't interpret, for writers that know what to do with them.
+    pub extra: HashMap<String, String>,
+    /// The interface netctl uses the profile on, which iwd doesn't bind networks to.
+    pub interface: Option<String>,
+}
+
+/// Keys of a profile that are interpreted by the parser.
+const KNOWN_KEYS: &[&str] = &[
+    "Description", "Connection", "Security", "ESSID", "Key", "WPAConfigSection", "Hidden", "Priority",
+    "Frequency", "ScanFrequencies", "MACAddress", "ExcludeAuto", "Interface",
+    "IP", "Address", "Netmask", "Gateway", "DNS", "DNSDomain", "DNSSearch", "Routes",
+    "IP6", "Address6", "Gateway6", "DNS6", "Routes6",
+];
+
+/// Options of a `WPAConfigSection` that are interpreted by the parser.
+const SECTION_KEYS: &[&str] = &[
+    "ssid", "scan_ssid", "key_mgmt", "psk", "sae_password", "eap", "phase2", "identity", "anonymous_identity",
+    "password", "ca_cert", "client_cert", "private_key", "private_key_passwd", "mac_addr", "freq_list",
+];
+
+/// Maximum length of an SSID in bytes, as defined by 802.11.
+pub const MAX_SSID_LENGTH: usize = 32;
+
+/// Get a string according to the netctl quoting rules.
+///
+/// See man netctl.profile, "SPECIAL QUOTING RULES" for definition. The shell quoting of the value
+/// has already been evaluated by `shell_values`, so only a literal leading `"` remains to be handled.
+///
+/// # Arguments
+///
+/// * `config` the ini file to read from.
+/// * `key` the key to read from the config
+///
+/// # Return value
+///
+/// The value, and whether it is a regular (quoted) string rather than a raw value. An empty
+/// value is an error, since it can't be a valid key.
+fn get_quoted_string<'a>(config: &'a HashMap<String, String>, key: &str) -> Result<(&'a str, bool), ConversionError> {
+    let contents = config.get(key).ok_or(ConversionError::MissingKeys)?;
+
+    let (value, quoted) = match contents.strip_prefix('"') {
+        Some(value) => (value, false),
+        None => (contents.as_str(), true),
+    };
+
+    if value.is_empty() {
+        Err(ConversionError::EmptyKey(key.to_owned()))
+    } else {
+        Ok((value, quoted))
+    }
+}
+
+/// Get a plain (non-key) value, with any shell quotes left over after ini parsing removed.
+///
+/// Profiles are sourced by the shell, so values like `'"wireless"'` are equivalent to `wireless`.
+/// The ini parser strips one layer of quotes, but nested quotes are preserved.
+fn get_unquoted<'a>(config: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    config.get(key).map(|contents| {
+        let contents = contents.as_str();
+        for quote in &['"', '\''] {
+            if contents.len() >= 2 && contents.starts_with(*quote) && contents.ends_with(*quote) {
+                return &contents[1..contents.len() - 1];
+            }
+        }
+
+        contents
+    })
+}
+
+/// Split a bash array like `('first' "second" third)` into its elements.
+///
+/// # Return value
+///
+/// The elements, or None if the value is not an array.
+fn parse_array(value: &str) -> Option<Vec<String>> {
+    let inner = value.strip_prefix('(')?.strip_suffix(')')?;
+    let mut elements = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in inner.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    elements.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        elements.push(current);
+    }
+
+    Some(elements)
+}
+
+/// Join bash arrays that span multiple lines, like most `WPAConfigSection`s, onto a single line.
+fn join_arrays(text: &str) -> String {
+    let mut joined = String::new();
+    let mut quote = None;
+    let mut open = false;
+
+    for line in text.lines() {
+        if open {
+            if !line.trim_start().starts_with('#') {
+                joined.push(' ');
+                joined.push_str(line.trim());
+                open = !closes_array(line, &mut quote);
+            }
+            continue;
+        }
+
+        joined.push_str(line);
+        joined.push('\n');
+
+        if let Some((_, value)) = line.split_once('=') {
+            if let Some(elements) = value.trim_start().strip_prefix('(') {
+                open = !closes_array(elements, &mut quote);
+                if open {
+                    joined.pop();
+                }
+            }
+        }
+    }
+
+    joined
+}
+
+/// Scan part of a bash array for its closing parenthesis, keeping track of quotes across lines.
+fn closes_array(fragment: &str, quote: &mut Option<char>) -> bool {
+    for c in fragment.chars() {
+        match *quote {
+            Some(q) if c == q => *quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => *quote = Some(c),
+            None if c == ')' => return true,
+            None => {}
+        }
+    }
+
+    false
+}
+
+/// Parse the wpa_supplicant network block of a `wpa-configsection` profile into its settings.
+///
+/// Quoted (string) values are unquoted; other values are kept as they are, except for an unquoted
+/// SSID, which is hex-encoded.
+fn parse_config_section(config: &HashMap<String, String>) -> Result<HashMap<String, String>, ConversionError> {
+    let section = config.get("WPAConfigSection").ok_or(ConversionError::MissingKeys)?;
+    let lines = parse_array(section)
+        .ok_or_else(|| ConversionError::ParseError("WPAConfigSection is not a valid array".to_owned(), None))?;
+
+    let mut settings = HashMap::new();
+    for line in lines {
+        if let Some((key, value)) = line.split_once('=') {
+            let (key, value) = (key.trim(), value.trim());
+            // The SSID keeps its quotes, to tell it from a hex one in `parse_section_ssid`.
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(unquoted) if key != "ssid" => unquoted.to_owned(),
+                _ => value.to_owned(),
+            };
+            settings.insert(key.to_owned(), value);
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Get the SSID from the `ssid` of a `WPAConfigSection`, either a quoted string or any bytes in hex.
+fn parse_section_ssid(value: &str) -> Result<Ssid, ConversionError> {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(ssid) => Ok(ssid.into()),
+        None => hex::decode(value).map(Ssid::from)
+            .map_err(|_| ConversionError::ParseError(format!("Invalid SSID in WPAConfigSection: {}", value), None)),
+    }
+}
+
+/// Translate a wpa_supplicant `phase2` setting, like `auth=MSCHAPV2`, to the iwd inner method.
+fn phase2_method(method: EAPMethod, phase2: &str) -> Result<String, ConversionError> {
+    let (kind, name) = phase2.split_once('=').unwrap_or(("auth", phase2));
+    let name = name.to_ascii_uppercase();
+
+    match (method, kind, name.as_str()) {
+        (EAPMethod::PEAP, _, "MSCHAPV2") | (EAPMethod::PEAP, _, "GTC") | (EAPMethod::PEAP, _, "MD5") => Ok(name),
+        (EAPMethod::TTLS, "autheap", "MSCHAPV2") | (EAPMethod::TTLS, "autheap", "GTC") | (EAPMethod::TTLS, "autheap", "MD5") => Ok(name),
+        (EAPMethod::TTLS, "auth", "PAP") => Ok("Tunneled-PAP".to_owned()),
+        (EAPMethod::TTLS, "auth", "CHAP") => Ok("Tunneled-CHAP".to_owned()),
+        (EAPMethod::TTLS, "auth", "MSCHAP") => Ok("Tunneled-MSCHAP".to_owned()),
+        (EAPMethod::TTLS, "auth", "MSCHAPV2") => Ok("Tunneled-MSCHAPv2".to_owned()),
+        _ => Err(ConversionError::Unsupported),
+    }
+}
+
+/// Whether a key is a precomputed PSK: 64 hex digits.
+///
+/// Passphrases are at most 63 characters, so this can't be mistaken for one.
+fn is_raw_psk(key: &str) -> bool {
+    key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Whether a wpa_supplicant network block is for a WPA3-Personal (SAE) network.
+fn is_sae(settings: &HashMap<String, String>) -> bool {
+    settings.get("key_mgmt").is_some_and(|key_mgmt| key_mgmt.split_whitespace().any(|k| k == "SAE"))
+}
+
+/// The `Security` types that the `key_mgmt` of a network block allows, like `wpa` for `WPA-PSK`,
+/// in the order they're listed. Enterprise authentication is `wpa-configsection`.
+fn key_mgmt_modes(settings: &HashMap<String, String>) -> Vec<&'static str> {
+    let mut modes = Vec::new();
+    for key_mgmt in settings.get("key_mgmt").map(String::as_str).unwrap_or("").split_whitespace() {
+        let mode = match key_mgmt {
+            "WPA-PSK" | "WPA-PSK-SHA256" | "FT-PSK" => "wpa",
+            "SAE" | "FT-SAE" => "sae",
+            "WPA-EAP" | "WPA-EAP-SHA256" | "FT-EAP" | "IEEE8021X" => "wpa-configsection",
+            _ => continue,
+        };
+        if !modes.contains(&mode) {
+            modes.push(mode);
+        }
+    }
+
+    modes
+}
+
+/// Get the passphrase of an SAE network block, which can't be a precomputed PSK.
+fn sae_passphrase(settings: &HashMap<String, String>) -> Result<String, ConversionError> {
+    if let Some(password) = settings.get("sae_password") {
+        return Ok(password.clone());
+    }
+
+    let psk = settings.get("psk").ok_or(ConversionError::MissingKeys)?;
+    if is_raw_psk(psk) {
+        Err(ConversionError::Unsupported)
+    } else {
+        Ok(psk.clone())
+    }
+}
+
+/// Build the enterprise security settings from a wpa_supplicant network block.
+fn eap_security(settings: &HashMap<String, String>) -> Result<EAPSecurity, ConversionError> {
+    let key_mgmt = settings.get("key_mgmt").map(String::as_str).unwrap_or("");
+    if !key_mgmt.split_whitespace().any(|k| k == "WPA-EAP" || k == "IEEE8021X") {
+        return Err(ConversionError::Unsupported);
+    }
+
+    // Only one method can be configured for iwd, so use the preferred one.
+    let method = settings.get("eap")
+        .and_then(|eap| eap.split_whitespace().next())
+        .ok_or(ConversionError::MissingKeys)?
+        .parse::<EAPMethod>()
+        .map_err(|_| ConversionError::Unsupported)?;
+
+    let phase2 = match (method, settings.get("phase2")) {
+        (EAPMethod::TLS, _) => None,
+        (_, Some(phase2)) => Some(phase2_method(method, phase2)?),
+        // wpa_supplicant tries all inner methods, of which this is by far the most common.
+        (EAPMethod::PEAP, None) => Some("MSCHAPV2".to_owned()),
+        (EAPMethod::TTLS, None) => return Err(ConversionError::MissingKeys),
+    };
+
+    let get = |key: &str| settings.get(key).cloned();
+
+    Ok(EAPSecurity {
+        method,
+        identity: get("identity"),
+        anonymous_identity: get("anonymous_identity"),
+        password: get("password"),
+        phase2,
+        ca_cert: get("ca_cert"),
+        client_cert: get("client_cert"),
+        client_key: get("private_key"),
+        client_key_passphrase: get("private_key_passwd"),
+    })
+}
+
+/// Get a value that may be a single value or an array, as a list of values.
+fn get_list(config: &HashMap<String, String>, key: &str) -> Vec<String> {
+    match config.get(key) {
+        Some(value) => parse_array(value).unwrap_or_else(|| vec![value.clone()]),
+        None => Vec::new(),
+    }
+}
+
+/// Parse the IP configuration of one address family.
+///
+/// # Arguments
+///
+/// * `config` the general section of the profile.
+/// * `v6` whether to parse the IPv6 (`IP6`, `Address6`, ...) or the IPv4 settings.
+fn parse_ip_config(config: &HashMap<String, String>, v6: bool) -> Result<IpConfig, ConversionError> {
+    let suffix = if v6 { "6" } else { "" };
+    let method_key = if v6 { "IP6" } else { "IP" };
+
+    match get_unquoted(config, method_key) {
+        None | Some("dhcp") | Some("dhcp-noaddr") => return Ok(IpConfig::Dhcp),
+        Some("stateless") if v6 => return Ok(IpConfig::Stateless),
+        Some("no") => return Ok(IpConfig::Disabled),
+        Some("static") => {}
+        Some(other) => return Err(ConversionError::InvalidIpConfig(format!("{}={}", method_key, other))),
+    }
+
+    let invalid = ConversionError::InvalidIpConfig;
+
+    // A separate netmask applies to addresses given without a prefix length.
+    let netmask_prefix = match get_unquoted(config, "Netmask").filter(|_| !v6) {
+        Some(netmask) => Some(netmask.parse().ok()
+            .and_then(prefix_from_netmask)
+            .ok_or_else(|| invalid(format!("Invalid netmask: {}", netmask)))?),
+        None => None,
+    };
+
+    let addresses = get_list(config, &format!("Address{}", suffix)).iter()
+        .map(|a| {
+            let mut address = a.parse::<Address>()?;
+            if let (false, Some(prefix)) = (a.contains('/'), netmask_prefix) {
+                address.prefix = prefix;
+            }
+            Ok(address)
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(invalid)?;
+
+    let gateway = get_unquoted(config, &format!("Gateway{}", suffix))
+        .map(|g| g.parse().map_err(|_| invalid(format!("Invalid gateway: {}", g))))
+        .transpose()?;
+
+    let routes = get_list(config, &format!("Routes{}", suffix)).iter()
+        .map(|r| r.parse())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+
+    // DNS servers of both families share the same key, but DNS6 is accepted for IPv6 as well.
+    let mut dns = Vec::new();
+    let mut servers = get_list(config, "DNS");
+    if v6 {
+        servers.extend(get_list(config, "DNS6"));
+    }
+
+    for server in servers {
+        let server: std::net::IpAddr = server.parse().map_err(|_| invalid(format!("Invalid DNS server: {}", server)))?;
+        if server.is_ipv6() == v6 {
+            dns.push(server);
+        }
+    }
+
+    // iwd has a single domain name, and no search list.
+    let domain_name = if v6 { None } else { dns_domains(config).into_iter().next() };
+
+    Ok(IpConfig::Static(StaticConfig { addresses, gateway, dns, routes, domain_name }))
+}
+
+/// The `DNSDomain`, followed by the `DNSSearch` domains that differ from it.
+fn dns_domains(config: &HashMap<String, String>) -> Vec<String> {
+    let mut domains: Vec<String> = Vec::new();
+    for key in &["DNSDomain", "DNSSearch"] {
+        for domain in get_unquoted(config, key).unwrap_or("").split_whitespace() {
+            if !domains.iter().any(|d| d == domain) {
+                domains.push(domain.to_owned());
+            }
+        }
+    }
+
+    domains
+}
+
+/// Parse a profile into a single network.
+///
+/// Profiles listing multiple ESSIDs produce the network for the first one; use `parse_networks`
+/// to get all of them.
+pub fn parse_network(input: &mut impl Read) -> Result<Network, ConversionError> {
+    let mut networks = parse_networks(input)?;
+    Ok(networks.swap_remove(0))
+}
+
+/// Parse a profile into networks, one per ESSID.
+///
+/// Most profiles have a single ESSID, but it may also be an array of equivalent networks
+/// sharing the same credentials.
+///
+/// # Return value
+///
+/// The networks in the order they were listed, which is never empty on success.
+pub fn parse_networks(input: &mut impl Read) -> Result<Vec<Network>, ConversionError> {
+    parse_profile(input, &ParseOptions::default()).map(|profile| profile.networks)
+}
+
+/// Get the SSID from a single ESSID value.
+///
+/// Following the netctl quoting rules, a value starting with a literal `"` is passed to
+/// wpa_supplicant as is: either a quoted string or a hex-encoded SSID.
+fn parse_essid(value: &str) -> Result<Ssid, ConversionError> {
+    match value.strip_prefix('"') {
+        Some(quoted) if quoted.ends_with('"') => Ok(quoted[..quoted.len() - 1].into()),
+        // Any bytes, which need not be UTF-8.
+        Some(hex) => hex::decode(hex).map(Ssid::from)
+            .map_err(|_| ConversionError::ParseError(format!("Invalid hex ESSID: {}", hex), None)),
+        None => Ok(value.into()),
+    }
+}
+
+/// Find the band that all frequencies in lists like `5180 5200`, as in `ScanFrequencies` or
+/// wpa_supplicant's `freq_list`, belong to.
+///
+/// # Return value
+///
+/// The band, or None if there are no frequencies or they span multiple bands.
+fn parse_band<'a>(lists: impl Iterator<Item=&'a str>) -> Result<Option<Band>, ConversionError> {
+    let mut bands = HashSet::new();
+
+    for frequency in lists.flat_map(str::split_whitespace) {
+        let band = frequency.parse::<u32>().ok()
+            .and_then(Band::from_frequency)
+            .ok_or_else(|| ConversionError::ParseError(format!("Invalid frequency: {}", frequency), None))?;
+        bands.insert(band);
+    }
+
+    Ok(match bands.len() {
+        1 => bands.into_iter().next(),
+        _ => None,
+    })
+}
+
+/// Whether a `$` followed by this character starts a shell expansion, like `$HOSTNAME` or `$(cmd)`.
+fn starts_expansion(c: char) -> bool {
+    c == '(' || c == '{' || c == '_' || c.is_ascii_alphabetic()
+}
+
+/// Get the name of the environment variable a value consists of, as in `${NAME}` or `$NAME`.
+fn env_reference(value: &str) -> Option<&str> {
+    let name = value.strip_prefix("${")
+        .and_then(|v| v.strip_suffix('}'))
+        .or_else(|| value.strip_prefix('$'))?;
+
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric());
+
+    if valid {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// Replace a Key referring to an environment variable with the value of that variable.
+///
+/// A leading `"`, marking a precomputed key, is kept in place.
+///
+/// # Return value
+///
+/// Whether the key was substituted.
+fn expand_env_secret(config: &mut HashMap<String, String>) -> Result<bool, ConversionError> {
+    let key = match config.get("Key") {
+        Some(key) => key,
+        None => return Ok(false),
+    };
+
+    let (prefix, reference) = match key.strip_prefix('"') {
+        Some(rest) => ("\"", rest),
+        None => ("", key.as_str()),
+    };
+
+    let name = match env_reference(reference) {
+        Some(name) => name,
+        None => return Ok(false),
+    };
+
+    let value = env::var(name).map_err(|_| ConversionError::UnsetVariable(name.to_owned()))?;
+    config.insert("Key".to_owned(), format!("{}{}", prefix, value));
+
+    Ok(true)
+}
+
+/// Whether a profile has a commented-out `Connection=wireless` line.
+fn has_disabled_connection(text: &str) -> bool {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix('#'))
+        .filter_map(|line| line.trim_start().strip_prefix("Connection="))
+        .any(|value| matches!(value.trim_end(), "wireless" | "'wireless'" | "\"wireless\""))
+}
+
+/// Fill in a missing or empty `Key` with the passphrase for the SSID of the profile, if there is one.
+fn complete_secret(config: &mut HashMap<String, String>, secrets: &HashMap<String, String>) {
+    if config.get("Key").is_some_and(|key| !key.is_empty()) {
+        return;
+    }
+
+    // Profiles with multiple SSIDs share a single key, so there is no SSID to look it up by.
+    let ssid = config.get("ESSID").filter(|ssid| parse_array(ssid).is_none()).and_then(|ssid| parse_essid(ssid).ok());
+    if let Some(passphrase) = ssid.as_ref().and_then(Ssid::to_str).and_then(|ssid| secrets.get(ssid)) {
+        config.insert("Key".to_owned(), passphrase.clone());
+    }
+}
+
+/// Evaluate the shell quoting of a value as written in the profile, like bash does when netctl
+/// sources it.
+///
+/// Backslashes escape any character outside of quotes, but only `\`, `"`, `$` and `` ` `` within
+/// double quotes, like `"pass\"word"`. Within single quotes, everything is literal. Variables
+/// are not expanded.
+///
+/// # Return value
+///
+/// The value, and whether it looks like it relies on shell expansion outside of single quotes,
+/// which bash would have evaluated.
+fn shell_unquote(raw: &str) -> Result<(String, bool), QuotingError> {
+    let mut chars = raw.trim().chars();
+    let mut value = String::new();
+    let mut quote = None;
+    // A comment starts a word after whitespace, but not one right after the `=`.
+    let mut previous = if raw.starts_with(char::is_whitespace) { ' ' } else { '=' };
+    let mut expansion = false;
+    // Length of the value without trailing unquoted whitespace.
+    let mut end = 0;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            // The rest of the line is a comment.
+            (None, '#') if previous.is_whitespace() => break,
+            // Backslashes are literal within single quotes.
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => value.push(c),
+            (None, '\\') => value.push(chars.next().ok_or(QuotingError::UnterminatedEscape)?),
+            (Some('"'), '\\') => match chars.next().ok_or(QuotingError::UnterminatedEscape)? {
+                escaped @ ('\\' | '"' | '$' | '`') => value.push(escaped),
+                other => {
+                    value.push('\\');
+                    value.push(other);
+                }
+            },
+            (Some('"'), '"') => quote = None,
+            (None, '`') | (Some('"'), '`') => {
+                expansion = true;
+                value.push(c);
+            }
+            (None, '$') | (Some('"'), '$') => {
+                expansion |= chars.clone().next().is_some_and(starts_expansion);
+                value.push(c);
+            }
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, c) if c.is_whitespace() => {
+                value.push(c);
+                previous = c;
+                continue;
+            }
+            _ => value.push(c),
+        }
+        previous = c;
+        end = value.len();
+    }
+
+    match quote {
+        Some(_) => Err(QuotingError::UnterminatedQuote),
+        None => {
+            value.truncate(end);
+            Ok((value, expansion))
+        }
+    }
+}
+
+/// Evaluate the quoting of the keys whose values are used verbatim, so they aren't mangled by the
+/// ini parser and malformed quoting is caught.
+///
+/// # Return value
+///
+/// The values of those keys as netctl would see them, and the keys that look like they rely on
+/// shell expansion. Arrays are left to `parse_array`.
+fn shell_values(text: &str) -> Result<(HashMap<String, String>, HashSet<String>), ConversionError> {
+    let mut values = HashMap::new();
+    let mut expansions = HashSet::new();
+
+    for line in text.lines() {
+        if let Some((key, value)) = line.trim_start().split_once('=') {
+            if key == "ESSID" || key == "Key" {
+                let (unquoted, expansion) = shell_unquote(value).map_err(|e| ConversionError::MalformedQuoting(key.to_owned(), e))?;
+                if expansion {
+                    expansions.insert(key.to_owned());
+                }
+                if !value.trim_start().starts_with('(') {
+                    values.insert(key.to_owned(), unquoted);
+                }
+            }
+        }
+    }
+
+    Ok((values, expansions))
+}
+
+/// What a `WPAConfigSection` contributes to the networks, besides their security.
+#[derive(Default)]
+struct SectionSettings {
+    ssid: Option<Ssid>,
+    hidden: bool,
+    frequencies: Option<String>,
+    mac_address: Option<MacAddress>,
+    options: Vec<(String, String)>,
+    /// A passphrase and a precomputed PSK that should be derived from it.
+    psk: Option<(String, String)>,
+    /// The `key_mgmt` of a `Security=wpa` profile, if it is ambiguous.
+    ambiguous_key_mgmt: Option<String>,
+}
+
+/// How strong a `Security` candidate is, for picking one when a profile lists several.
+///
+/// A configuration section (EAP or SAE) beats a WPA3 passphrase, which beats a WPA2 passphrase,
+/// which beats WEP and any unknown type, which beat an open network.
+fn security_strength(kind: &str) -> u8 {
+    match kind {
+        "wpa-configsection" => 4,
+        "sae" => 3,
+        "wpa" => 2,
+        "none" => 0,
+        _ => 1,
+    }
+}
+
+/// Parse the security of a profile for one `Security` type.
+fn parse_security(kind: &str, contents: &HashMap<String, String>, section: &mut SectionSettings) -> Result<Security, ConversionError> {
+    Ok(match kind {
+        "none" => Security::Open,
+        "wpa" => {
+            // A WPAConfigSection can change what kind of WPA the profile is for.
+            let settings = match contents.get("WPAConfigSection") {
+                Some(_) => parse_config_section(contents)?,
+                None => HashMap::new(),
+            };
+            let modes = key_mgmt_modes(&settings);
+            let personal = modes.iter().any(|mode| *mode != "wpa-configsection");
+
+            if personal && modes.contains(&"wpa-configsection") {
+                section.ambiguous_key_mgmt = settings.get("key_mgmt").cloned();
+            }
+
+            // Mixed WPA2/WPA3 networks are stored alike, and iwd picks SAE if it can.
+            if modes.is_empty() || modes.contains(&"wpa") {
+                let (key, quoted) = get_quoted_string(contents, "Key")?;
+                let passphrase = if quoted && !is_raw_psk(key) {
+                    PSKSecurity::Password(key.to_owned())
+                } else {
+                    PSKSecurity::PSK(key.to_owned())
+                };
+                Security::PSK(passphrase)
+            } else if personal {
+                match get_quoted_string(contents, "Key") {
+                    Ok((passphrase, true)) => Security::SAE(passphrase.to_owned()),
+                    Ok((_, false)) => return Err(ConversionError::Unsupported),
+                    Err(_) => Security::SAE(sae_passphrase(&settings)?),
+                }
+            } else {
+                Security::EAP(eap_security(&settings)?)
+            }
+        }
+        "sae" => match get_quoted_string(contents, "Key")? {
+            (passphrase, true) => Security::SAE(passphrase.to_owned()),
+            // SAE always derives its keys from the passphrase itself.
+            (_, false) => return Err(ConversionError::Unsupported),
+        },
+        "wpa-configsection" => {
+            let settings = parse_config_section(contents)?;
+            section.ssid = settings.get("ssid").map(|ssid| parse_section_ssid(ssid)).transpose()?;
+            section.hidden = settings.get("scan_ssid").is_some_and(|scan| scan == "1");
+            section.frequencies = settings.get("freq_list").cloned();
+            section.options = settings.iter()
+                .filter(|(key, _)| !SECTION_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            section.options.sort();
+            // 1 randomizes for each connection, 2 for each network, and iwd only does the former.
+            section.mac_address = match settings.get("mac_addr").map(String::as_str) {
+                Some("1") | Some("2") => Some(MacAddress::Random),
+                Some(_) => Some(MacAddress::Permanent),
+                None => None,
+            };
+            if is_sae(&settings) {
+                let passphrase = sae_passphrase(&settings)?;
+                // Mixed WPA2/WPA3 blocks have both.
+                section.psk = settings.get("psk").filter(|psk| is_raw_psk(psk)).map(|psk| (passphrase.clone(), psk.to_ascii_lowercase()));
+                Security::SAE(passphrase)
+            } else {
+                Security::EAP(eap_security(&settings)?)
+            }
+        }
+        // iwd deliberately has no WEP support at all, so there is nothing to convert to.
+        "wep" => return Err(ConversionError::UnsupportedWEP),
+        _ => return Err(ConversionError::Unsupported)
+    })
+}
+
+/// Parse a profile into its networks and any warnings about it.
+pub fn parse_profile(input: &mut impl Read, options: &ParseOptions) -> Result<Profile, ConversionError> {
+    let mut text = String::new();
+    input.read_to_string(&mut text).map_err(|e| match e.kind() {
+        ErrorKind::InvalidData => ConversionError::InvalidUtf8,
+        _ => ConversionError::ParseError(e.to_string(), Some(Box::new(e))),
+    })?;
+    // Profiles edited on Windows may have a byte order mark and CRLF line endings, which would
+    // otherwise end up in the first key and the last value of each line.
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text).replace("\r\n", "\n");
+    let (values, expansions) = shell_values(&text)?;
+
+    let contents = Ini::load_from_str(&join_arrays(&text))?;
+    let contents = match contents.section(None::<String>) {
+        Some(contents) if !contents.is_empty() => contents,
+        _ => return Err(ConversionError::EmptyProfile),
+    };
+
+    let mut contents = contents.clone();
+    contents.extend(values);
+    // Before completing it from the secrets, which aren't tied to a security type.
+    let has_key = contents.get("Key").is_some_and(|key| !key.is_empty());
+    let expanded = options.expand_env_secrets && expand_env_secret(&mut contents)?;
+    complete_secret(&mut contents, &options.secrets);
+    let contents = &contents;
+
+    // Commenting out the connection type is a common way of disabling a profile.
+    let disabled = !contents.contains_key("Connection") && has_disabled_connection(&text);
+    match get_unquoted(contents, "Connection") {
+        Some("wireless") => (),
+        Some("ethernet") => return Err(ConversionError::Wired),
+        _ if !disabled => return Err(ConversionError::NotWireless),
+        _ => (),
+    }
+
+    // Profiles may list several candidates, like `Security=('wpa' 'wep')`, of which the strongest
+    // that converts is used.
+    // A key without a security type is most likely WPA, and certainly not an open network.
+    let assumed = match (contents.get("Security"), has_key, &options.assume_security) {
+        (None, true, Some(kind)) => Some(kind.clone()),
+        (None, true, None) => return Err(ConversionError::MissingSecurity),
+        _ => None,
+    };
+    let mut kinds = match &assumed {
+        Some(kind) => vec![kind.clone()],
+        None => contents.get("Security")
+            .and_then(|security| parse_array(security))
+            .filter(|kinds| !kinds.is_empty())
+            .unwrap_or_else(|| vec![get_unquoted(contents, "Security").unwrap_or("none").to_owned()]),
+    };
+    // Types of equal strength keep their order, so duplicates are dropped before sorting.
+    let mut seen = HashSet::new();
+    kinds.retain(|kind| seen.insert(kind.clone()));
+    kinds.sort_by_key(|kind| Reverse(security_strength(kind)));
+
+    let mut section = SectionSettings::default();
+    let mut securities: Vec<Security> = Vec::new();
+    let mut ignored = Vec::new();
+    let mut first_error = None;
+    for kind in &kinds {
+        match parse_security(kind, contents, &mut section) {
+            // iwd has one file per SSID and extension, so weaker candidates with the same
+            // extension can't be kept, even with `expand_security`.
+            Ok(security) if securities.iter().any(|s| s.get_extension() == security.get_extension()) => ignored.push(kind.clone()),
+            Ok(_) if !securities.is_empty() && !options.expand_security => ignored.push(kind.clone()),
+            Ok(security) => securities.push(security),
+            Err(e) => {
+                ignored.push(kind.clone());
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if securities.is_empty() {
+        return Err(first_error.expect("there is always at least one candidate"));
+    }
+
+    let mut warnings = Vec::new();
+    if disabled {
+        warnings.push(Warning::DisabledProfile);
+    }
+    if let Some(kind) = assumed {
+        warnings.push(Warning::AssumedSecurity(kind));
+    }
+    if !ignored.is_empty() {
+        let used = securities.iter().map(|security| security.type_name().to_owned()).collect();
+        warnings.push(Warning::IgnoredSecurities(used, ignored));
+    }
+    let checked_keys: &[&str] = if expanded { &["ESSID"] } else { &["ESSID", "Key"] };
+    for key in checked_keys {
+        if expansions.contains(*key) {
+            warnings.push(Warning::ShellExpansion(key.to_string()));
+        }
+    }
+
+    let priority = match get_unquoted(contents, "Priority") {
+        Some(priority) => priority.parse::<i32>()
+            .map_err(|e| ConversionError::ParseError(format!("Invalid Priority: {}", priority), Some(Box::new(e))))?,
+        None => 0,
+    };
+    if priority != 0 {
+        warnings.push(Warning::UnsupportedPriority(priority));
+    }
+
+    let frequencies = ["Frequency", "ScanFrequencies"].iter()
+        .filter_map(|key| get_unquoted(contents, key))
+        .chain(section.frequencies.as_deref());
+    let band = parse_band(frequencies)?;
+
+    let mac_address = match get_unquoted(contents, "MACAddress") {
+        Some(mac_address) => mac_address.parse::<MacAddress>().map_err(|e| ConversionError::ParseError(e, None))?,
+        None => section.mac_address.unwrap_or_default(),
+    };
+    if let Some(band) = band {
+        warnings.push(Warning::UnsupportedBand(band));
+    }
+
+    let extra: HashMap<String, String> = contents.iter()
+        .filter(|(key, _)| !KNOWN_KEYS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let mut keys: Vec<_> = contents.keys().filter(|key| KNOWN_KEYS.contains(&key.as_str())).cloned().collect();
+    keys.sort();
+    if !extra.is_empty() {
+        let mut ignored: Vec<_> = extra.keys().cloned().collect();
+        ignored.sort();
+        warnings.push(Warning::IgnoredKeys(ignored));
+    }
+
+    let ssids = match contents.get("ESSID") {
+        Some(ssid) => match parse_array(ssid) {
+            Some(mut ssids) => {
+                let mut seen = HashSet::new();
+                ssids.retain(|ssid| seen.insert(ssid.clone()));
+                ssids.into_iter().map(Ssid::from).collect()
+            }
+            None => vec![parse_essid(ssid)?],
+        },
+        None => section.ssid.take().into_iter().collect(),
+    };
+
+    if ssids.is_empty() {
+        return Err(ConversionError::MissingSSID);
+    }
+
+    // 802.11 limits SSIDs to 32 bytes, so a longer one can never match.
+    if let Some(ssid) = ssids.iter().find(|ssid| ssid.is_empty() || ssid.len() > MAX_SSID_LENGTH) {
+        return Err(ConversionError::InvalidSSID(ssid.to_string()));
+    }
+
+    // The PSK is derived from the SSID, so it can't be valid for all of them.
+    if ssids.len() > 1 && securities.iter().any(|security| matches!(security, Security::PSK(PSKSecurity::PSK(_)))) {
+        return Err(ConversionError::SharedRawPSK);
+    }
+
+    let domains = dns_domains(contents);
+    match get_unquoted(contents, "IP") {
+        Some("static") if domains.len() > 1 => warnings.push(Warning::IgnoredDomains(domains[1..].to_vec())),
+        Some("static") => {}
+        _ if !domains.is_empty() => warnings.push(Warning::IgnoredDomains(domains)),
+        _ => {}
+    }
+
+    if let Some(key_mgmt) = section.ambiguous_key_mgmt.take() {
+        let used = securities.iter().map(|security| security.type_name()).collect::<Vec<_>>().join(", ");
+        warnings.push(Warning::AmbiguousKeyMgmt(key_mgmt, used));
+    }
+
+    if let Some((passphrase, psk)) = &section.psk {
+        let mismatched = ssids.iter()
+            .filter(|ssid| hex::encode(compute_psk(ssid.as_bytes(), passphrase.as_bytes())) != *psk)
+            .map(|ssid| Warning::MismatchedPSK(ssid.to_string()));
+        warnings.extend(mismatched);
+    }
+
+    if options.strict {
+        if let Some(warning) = warnings.into_iter().next() {
+            return Err(ConversionError::Strict(warning));
+        }
+        warnings = Vec::new();
+    }
+
+    let description = get_unquoted(contents, "Description");
+    let hidden = section.hidden || matches!(get_unquoted(contents, "Hidden"), Some("yes") | Some("true") | Some("1"));
+    // Profiles excluded from netctl-auto are only ever started by hand.
+    let auto_connect = !disabled && !matches!(get_unquoted(contents, "ExcludeAuto"), Some("yes") | Some("true") | Some("1"));
+    let ipv4 = parse_ip_config(contents, false)?;
+    let ipv6 = parse_ip_config(contents, true)?;
+
+    let networks = ssids.iter()
+        .flat_map(|ssid| securities.iter().map(move |security| (ssid, security)))
+        .map(|(ssid, security)| {
+            let builder = Network::builder()
+                .security(security.clone())
+                .ssid(ssid.clone())
+                .hidden(hidden)
+                .auto_connect(auto_connect)
+                .ipv4(ipv4.clone())
+                .ipv6(ipv6.clone())
+                .priority(priority)
+                .band(band)
+                .mac_address(mac_address)
+                .wpa_options(section.options.clone());
+
+            match description {
+                Some(description) => builder.description(description),
+                None => builder,
+            }.build()
+        })
+        .collect::<Result<_, _>>()?;
+
+    let interface = get_unquoted(contents, "Interface").map(str::to_owned);
+
+    Ok(Profile { networks, warnings, keys, extra, interface })
+}
+
+/// Write a netctl profile for a network, the inverse of `parse_network`.
+///
+/// netctl needs to know the interface to use, which networks don't record. Only open and PSK
+/// networks, and static IPv4 configurations, are supported.
+pub fn write_profile(network: &Network, interface: &str) -> Result<String, ConversionError> {
+    let mut lines = Vec::new();
+    if let Some(description) = network.description() {
+        lines.push(format!("Description={}", shell_quote(description)));
+    }
+    lines.push(format!("Interface={}", shell_quote(interface)));
+    lines.push("Connection=wireless".to_owned());
+
+    match network.security() {
+        Security::Open => lines.push("Security=none".to_owned()),
+        Security::PSK(security) => {
+            lines.push("Security=wpa".to_owned());
+            match security {
+                // A leading literal quote would make netctl treat the passphrase as a raw key.
+                PSKSecurity::Password(passphrase) if passphrase.starts_with('"') => return Err(ConversionError::Unsupported),
+                PSKSecurity::Password(passphrase) => lines.push(format!("Key={}", shell_quote(passphrase))),
+                PSKSecurity::PSK(psk) => lines.push(format!("Key=\\\"{}", psk)),
+            }
+        }
+        _ => return Err(ConversionError::Unsupported),
+    }
+
+    // The same rule applies to the SSID, which can be given in hex instead.
+    match network.ssid().to_str() {
+        Some(ssid) if !ssid.starts_with('"') => lines.push(format!("ESSID={}", shell_quote(ssid))),
+        _ => lines.push(format!("ESSID=\\\"{}", hex::encode(network.ssid().as_bytes()))),
+    }
+
+    if network.hidden() {
+        lines.push("Hidden=yes".to_owned());
+    }
+
+    if !network.auto_connect() {
+        lines.push("ExcludeAuto=yes".to_owned());
+    }
+
+    match network.ipv4() {
+        IpConfig::Dhcp => lines.push("IP=dhcp".to_owned()),
+        IpConfig::Static(static_config) => {
+            lines.push("IP=static".to_owned());
+            let addresses: Vec<_> = static_config.addresses.iter().map(|a| shell_quote(&a.to_string())).collect();
+            lines.push(format!("Address=({})", addresses.join(" ")));
+            if let Some(gateway) = static_config.gateway {
+                lines.push(format!("Gateway='{}'", gateway));
+            }
+            if !static_config.dns.is_empty() {
+                let dns: Vec<_> = static_config.dns.iter().map(|d| format!("'{}'", d)).collect();
+                lines.push(format!("DNS=({})", dns.join(" ")));
+            }
+            if let Some(domain_name) = &static_config.domain_name {
+                lines.push(format!("DNSDomain={}", shell_quote(domain_name)));
+            }
+        }
+        _ => return Err(ConversionError::InvalidIpConfig("only DHCP and static IPv4 can be written".to_owned())),
+    }
+
+    lines.push(String::new());
+    Ok(lines.join("\n"))
+}
+
+/// Quote a value for the shell, so it is taken literally.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ip::Route;
+    use crate::networks::compute_psk;
+    use crate::networks::HexCase;
+    use crate::networks::WriteOptions;
+
+    use super::*;
+
+    /// Only the value of `shell_unquote`.
+    fn unquote(raw: &str) -> Result<String, QuotingError> {
+        shell_unquote(raw).map(|(value, _)| value)
+    }
+
+    #[test]
+    fn test_get_quoted_string() {
+        let sample = b"quoted=quoted_value\nnon_quoted=\\\"non_quoted_value\n";
+        let ini = Ini::read_from(&mut sample.as_ref()).unwrap();
+        let contents = ini.general_section();
+
+        let (value, quoted) = get_quoted_string(contents, "quoted").unwrap();
+        assert_eq!("quoted_value", value);
+        assert!(quoted);
+
+        let (value, quoted) = get_quoted_string(contents, "non_quoted").unwrap();
+        assert_eq!("non_quoted_value", value);
+        assert!(!quoted);
+    }
+
+    #[test]
+    fn test_empty_key() {
+        for key in &["Key=", "Key=''", "Key=\\\""] {
+            let sample = format!("Connection=wireless\nESSID=foo_network\nSecurity=wpa\n{}\n", key);
+            assert!(matches!(parse_networks(&mut sample.as_bytes()), Err(ConversionError::EmptyKey(_))), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_parse_network() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nKey=foo_password\nSecurity=wpa";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+
+        let correct_network = Network::new("foo_network".to_string(),
+                                           Security::PSK(PSKSecurity::Password("foo_password".to_string())));
+
+        assert_eq!(correct_network, network);
+    }
+
+    #[test]
+    fn test_parse_quoted_values() {
+        let sample = b"Connection=\"wireless\"\nESSID=foo_network\nKey=foo_password\nSecurity=\"wpa\"";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("foo_password".to_string())), network.security());
+
+        let sample = b"Connection='\"wireless\"'\nESSID=foo_network\nSecurity='\"none\"'";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&Security::Open, network.security());
+    }
+
+    #[test]
+    fn test_parse_quoted_essid() {
+        let sample = b"Connection=wireless\nESSID=\"My Network\"\nKey=foo_password\nSecurity=wpa";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!("My Network", network.ssid());
+        assert_eq!("My Network.psk", network.iwd_file_name(HexCase::Lower));
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        let psk = hex::encode(compute_psk(b"My Network", b"foo_password"));
+        assert_eq!(Some(psk.as_str()), config.get_from(Some("Security"), "PreSharedKey"));
+    }
+
+    #[test]
+    fn test_parse_empty_profile() {
+        let sample = b"# Connection=wireless\n# ESSID=foo_network\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::EmptyProfile)));
+        assert!(matches!(parse_network(&mut b"".as_ref()), Err(ConversionError::EmptyProfile)));
+
+        // Like an iwd file passed by mistake.
+        let sample = b"[Security]\n[Settings]\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::EmptyProfile)));
+        let sample = b"[Security]\nPassphrase=foo_password\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::EmptyProfile)));
+    }
+
+    #[test]
+    fn test_parse_array() {
+        assert_eq!(None, parse_array("foo"));
+        assert_eq!(Some(vec!["a b".to_owned(), "c".to_owned(), "d".to_owned()]),
+                   parse_array("('a b' \"c\" d)"));
+    }
+
+    #[test]
+    fn test_multiple_essids() {
+        let sample = b"Connection=wireless\nESSID=('Corp' 'Corp-5G' 'Corp')\nKey=foo_password\nSecurity=wpa\n";
+        let networks = parse_networks(&mut sample.as_ref()).unwrap();
+
+        let ssids: Vec<_> = networks.iter().map(|n| n.ssid()).collect();
+        assert_eq!(vec!["Corp", "Corp-5G"], ssids);
+        for network in &networks {
+            assert_eq!(&Security::PSK(PSKSecurity::Password("foo_password".to_owned())), network.security());
+        }
+
+        let sample = b"Connection=wireless\nESSID=('Corp' 'Corp-5G')\nKey=\\\"0123\nSecurity=wpa\n";
+        assert!(matches!(parse_networks(&mut sample.as_ref()), Err(ConversionError::SharedRawPSK)));
+    }
+
+    #[test]
+    fn test_shell_expansion() {
+        let sample = b"Connection=wireless\nESSID=$HOSTNAME\nSecurity=none\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(vec![Warning::ShellExpansion("ESSID".to_owned())], profile.warnings);
+
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        assert!(matches!(parse_profile(&mut sample.as_ref(), &strict), Err(ConversionError::Strict(_))));
+
+        let expansion = |raw: &str| shell_unquote(raw).unwrap().1;
+        assert!(expansion("$(hostname)"));
+        assert!(expansion("`hostname`"));
+        assert!(expansion("\"pass${word}\""));
+        assert!(!expansion("price: 5$"));
+        // Bash expands nothing within single quotes, or after a backslash.
+        assert!(!expansion("'pa$$word1'"));
+        assert!(!expansion("'`hostname`'"));
+        assert!(!expansion("pa\\$word"));
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey='pa$$word1'\n";
+        let profile = parse_profile(&mut sample.as_ref(), &strict).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("pa$$word1".to_owned())), profile.networks[0].security());
+    }
+
+    #[test]
+    fn test_ssid_length() {
+        let ssid = "a".repeat(40);
+        let sample = format!("Connection=wireless\nESSID={}\nSecurity=none\n", ssid);
+        assert!(matches!(parse_network(&mut sample.as_bytes()), Err(ConversionError::InvalidSSID(s)) if s == ssid));
+
+        // The limit applies to the decoded SSID, not its hex encoding.
+        let sample = format!("Connection=wireless\nESSID=\\\"{}\nSecurity=none\n", hex::encode("a".repeat(32)));
+        assert_eq!("a".repeat(32).as_str(), parse_network(&mut sample.as_bytes()).unwrap().ssid());
+
+        let sample = b"Connection=wireless\nESSID=''\nSecurity=none\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::InvalidSSID(_))));
+    }
+
+    #[test]
+    fn test_band() {
+        let sample = b"Connection=wireless\nESSID=fast_network\nSecurity=none\nScanFrequencies='5180 5200 5745'\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(Some(Band::Ghz5), profile.networks[0].band());
+        assert_eq!(vec![Warning::UnsupportedBand(Band::Ghz5)], profile.warnings);
+
+        let sample = b"Connection=wireless\nESSID=mixed_network\nSecurity=none\nScanFrequencies='2412 5180'\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(None, profile.networks[0].band());
+        assert!(profile.warnings.is_empty());
+
+        let sample = b"Connection=wireless\nESSID=fast_network\nSecurity=none\nFrequency=lots\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::ParseError(..))));
+    }
+
+    #[test]
+    fn test_mac_address() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nMACAddress=00:11:22:33:44:55\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(MacAddress::Fixed([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]), network.mac_address());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nMACAddress=random\n";
+        assert_eq!(MacAddress::Random, parse_network(&mut sample.as_ref()).unwrap().mac_address());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nMACAddress=nonsense\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::ParseError(..))));
+    }
+
+    #[test]
+    fn test_write_profile() {
+        let network = Network::builder()
+            .ssid("Bob's network")
+            .security(Security::PSK(PSKSecurity::Password("foo_password".to_owned())))
+            .build()
+            .unwrap();
+
+        let profile = write_profile(&network, "wlan0").unwrap();
+        assert_eq!("Interface='wlan0'\nConnection=wireless\nSecurity=wpa\nKey='foo_password'\nESSID='Bob'\\''s network'\nIP=dhcp\n",
+                   profile);
+        assert_eq!(network, parse_network(&mut profile.as_bytes()).unwrap());
+
+        let network = network.with_security(Security::PSK(PSKSecurity::PSK("0123".repeat(16))));
+        let profile = write_profile(&network, "wlan0").unwrap();
+        assert_eq!(network, parse_network(&mut profile.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_exclude_auto() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nExcludeAuto=yes\n";
+        assert!(!parse_network(&mut sample.as_ref()).unwrap().auto_connect());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nExcludeAuto=no\n";
+        assert!(parse_network(&mut sample.as_ref()).unwrap().auto_connect());
+    }
+
+    #[test]
+    fn test_disabled_profile() {
+        let sample = b"#Connection=wireless\nESSID=foo_network\nSecurity=none\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert!(!profile.networks[0].auto_connect());
+        assert_eq!(vec![Warning::DisabledProfile], profile.warnings);
+
+        let sample = b"# Connection=ethernet\nInterface=eth0\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::NotWireless)));
+    }
+
+    #[test]
+    fn test_wired_profile() {
+        let sample = b"Connection=ethernet\nInterface=eth0\nIP=dhcp\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::Wired)));
+
+        let sample = b"Connection=bond\nInterface=bond0\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::NotWireless)));
+    }
+
+    #[test]
+    fn test_missing_security() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nKey=foo_password\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::MissingSecurity)));
+
+        let options = ParseOptions { assume_security: Some("wpa".to_owned()), ..ParseOptions::default() };
+        let profile = parse_profile(&mut sample.as_ref(), &options).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("foo_password".to_owned())), profile.networks[0].security());
+        assert_eq!(vec![Warning::AssumedSecurity("wpa".to_owned())], profile.warnings);
+
+        // Without a key, the network is open.
+        let sample = b"Connection=wireless\nESSID=foo_network\n";
+        let profile = parse_profile(&mut sample.as_ref(), &options).unwrap();
+        assert_eq!(&Security::Open, profile.networks[0].security());
+        assert!(profile.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_passphrase_length() {
+        let profile = |key: &str| format!("Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey='{}'\n", key);
+
+        let short = profile("1234567");
+        assert!(matches!(parse_network(&mut short.as_bytes()), Err(ConversionError::InvalidPassphrase)));
+        let long = profile(&"x".repeat(64));
+        assert!(matches!(parse_network(&mut long.as_bytes()), Err(ConversionError::InvalidPassphrase)));
+
+        for key in &["12345678".to_owned(), "x".repeat(63)] {
+            assert!(parse_network(&mut profile(key).as_bytes()).is_ok());
+        }
+        // 64 hexadecimal digits are a precomputed PSK instead.
+        let psk = parse_network(&mut profile(&"a".repeat(64)).as_bytes()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::PSK("a".repeat(64))), psk.security());
+    }
+
+    #[test]
+    fn test_raw_psk_format() {
+        let profile = |key: &str| format!("Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=\\\"{}\n", key);
+
+        for key in &["zzzz".to_owned(), "a".repeat(63), "a".repeat(65), "z".repeat(64)] {
+            assert!(matches!(parse_network(&mut profile(key).as_bytes()), Err(ConversionError::InvalidPSK)), "{}", key);
+        }
+        let psk = parse_network(&mut profile(&"0123".repeat(16)).as_bytes()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::PSK("0123".repeat(16))), psk.security());
+    }
+
+    #[test]
+    fn test_wpa_key_mgmt() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n\
+                       WPAConfigSection=('key_mgmt=WPA-PSK')\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("foo_password".to_owned())), profile.networks[0].security());
+        assert!(profile.warnings.is_empty());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=\"foo_password\"\n\
+                       WPAConfigSection=('key_mgmt=SAE')\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&Security::SAE("foo_password".to_owned()), network.security());
+
+        let sample = b"Connection=wireless\nESSID=eduroam\nSecurity=wpa\n\
+                       WPAConfigSection=('key_mgmt=WPA-EAP' 'eap=PEAP' 'identity=\"user\"' 'password=\"secret\"')\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert!(matches!(network.security(), Security::EAP(_)));
+
+        // Both personal and enterprise: the passphrase is what `Security=wpa` stands for.
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=\"foo_password\"\n\
+                       WPAConfigSection=('key_mgmt=WPA-PSK WPA-EAP' 'eap=PEAP')\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("foo_password".to_owned())), profile.networks[0].security());
+        assert_eq!(vec![Warning::AmbiguousKeyMgmt("WPA-PSK WPA-EAP".to_owned(), "psk".to_owned())], profile.warnings);
+    }
+
+    #[test]
+    fn test_multiple_securities() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=('none' 'wep' 'wpa')\nKey=foo_password\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(1, profile.networks.len());
+        assert_eq!(&Security::PSK(PSKSecurity::Password("foo_password".to_owned())), profile.networks[0].security());
+        let ignored = vec!["wep".to_owned(), "none".to_owned()];
+        assert_eq!(vec![Warning::IgnoredSecurities(vec!["psk".to_owned()], ignored)], profile.warnings);
+
+        let options = ParseOptions { expand_security: true, ..ParseOptions::default() };
+        let profile = parse_profile(&mut sample.as_ref(), &options).unwrap();
+        let securities: Vec<_> = profile.networks.iter().map(|network| network.security().type_name()).collect();
+        assert_eq!(vec!["psk", "open"], securities);
+        assert_eq!(vec![Warning::IgnoredSecurities(vec!["psk".to_owned(), "open".to_owned()], vec!["wep".to_owned()])], profile.warnings);
+
+        // Repeated types are only tried and reported once.
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=('wep' 'foo' 'wep' 'wpa')\nKey=foo_password\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        let ignored = vec!["wep".to_owned(), "foo".to_owned()];
+        assert_eq!(vec![Warning::IgnoredSecurities(vec!["psk".to_owned()], ignored)], profile.warnings);
+
+        // SAE and WPA2 both convert to a .psk file.
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=('wpa' 'sae')\nKey=foo_password\n";
+        let profile = parse_profile(&mut sample.as_ref(), &options).unwrap();
+        assert_eq!(1, profile.networks.len());
+        assert_eq!(&Security::SAE("foo_password".to_owned()), profile.networks[0].security());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=('wep')\nKey=foo_password\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::UnsupportedWEP)));
+    }
+
+    #[test]
+    fn test_priority() {
+        let sample = b"Connection=wireless\nESSID=open_network\nSecurity=none\nPriority=-2\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(-2, profile.networks[0].priority());
+        assert_eq!(vec![Warning::UnsupportedPriority(-2)], profile.warnings);
+
+        let sample = b"Connection=wireless\nESSID=open_network\nSecurity=none\nPriority=0\n";
+        assert!(parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap().warnings.is_empty());
+
+        let sample = b"Connection=wireless\nESSID=open_network\nSecurity=none\nPriority=high\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::ParseError(..))));
+    }
+
+    #[test]
+    fn test_parse_static_ip() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=none\nIP=static\n\
+            Address=('192.168.1.10/24' '192.168.1.11/24')\nGateway='192.168.1.1'\n\
+            DNS=('192.168.1.1' '2001:db8::1')\nRoutes=('10.0.0.0/8 via 192.168.1.2')\nIP6=no\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+
+        let config = match network.ipv4() {
+            IpConfig::Static(config) => config,
+            other => panic!("Expected static configuration, got {:?}", other),
+        };
+        assert_eq!(vec!["192.168.1.10/24".parse::<Address>().unwrap(), "192.168.1.11/24".parse().unwrap()],
+                   config.addresses);
+        assert_eq!(Some([192, 168, 1, 1].into()), config.gateway);
+        assert_eq!(vec![std::net::IpAddr::from([192, 168, 1, 1])], config.dns);
+        assert_eq!(vec!["10.0.0.0/8 via 192.168.1.2".parse::<Route>().unwrap()], config.routes);
+        assert_eq!(&IpConfig::Disabled, network.ipv6());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress=nonsense\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::InvalidIpConfig(_))));
+    }
+
+    #[test]
+    fn test_expand_env_secrets() {
+        env::set_var("NETCTL2IWD_TEST_PSK", "injected_password");
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=${NETCTL2IWD_TEST_PSK}\n";
+        let options = ParseOptions { expand_env_secrets: true, ..Default::default() };
+
+        let profile = parse_profile(&mut sample.as_ref(), &options).unwrap();
+        assert!(profile.warnings.is_empty());
+        assert_eq!(&Security::PSK(PSKSecurity::Password("injected_password".to_owned())), profile.networks[0].security());
+
+        let mut config = Ini::new();
+        profile.networks[0].write_config(&mut config, &WriteOptions::default());
+        let expected = hex::encode(compute_psk(b"foo_network", b"injected_password"));
+        assert_eq!(Some(expected.as_str()), config.get_from(Some("Security"), "PreSharedKey"));
+
+        // Without the flag, the reference is kept literally and warned about.
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(vec![Warning::ShellExpansion("Key".to_owned())], profile.warnings);
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=$NETCTL2IWD_TEST_UNSET\n";
+        assert!(matches!(parse_profile(&mut sample.as_ref(), &options), Err(ConversionError::UnsetVariable(_))));
+    }
+
+    #[test]
+    fn test_extra_keys() {
+        let sample = b"Description='Home'\nInterface=wlan0\nConnection=wireless\nESSID=foo_network\nTimeoutWPA=30\nExecUpPost=true\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+
+        assert_eq!(Some("Home"), profile.networks[0].description());
+        assert_eq!(Some("wlan0"), profile.interface.as_deref());
+        let mut extra: Vec<_> = profile.extra.keys().map(String::as_str).collect();
+        extra.sort();
+        assert_eq!(vec!["ExecUpPost", "TimeoutWPA"], extra);
+
+        let ignored = vec!["ExecUpPost".to_owned(), "TimeoutWPA".to_owned()];
+        assert_eq!(vec![Warning::IgnoredKeys(ignored)], profile.warnings);
+        assert_eq!("Ignored keys: ExecUpPost, TimeoutWPA", profile.warnings[0].to_string());
+
+        let strict = ParseOptions { strict: true, ..Default::default() };
+        assert!(matches!(parse_profile(&mut sample.as_ref(), &strict), Err(ConversionError::Strict(Warning::IgnoredKeys(_)))));
+    }
+
+    #[test]
+    fn test_malformed_quoting() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\\\n";
+        assert!(matches!(parse_networks(&mut sample.as_ref()),
+                         Err(ConversionError::MalformedQuoting(key, QuotingError::UnterminatedEscape)) if key == "Key"));
+
+        let sample = b"Connection=wireless\nESSID='foo_network\nSecurity=none\n";
+        assert!(matches!(parse_networks(&mut sample.as_ref()),
+                         Err(ConversionError::MalformedQuoting(key, QuotingError::UnterminatedQuote)) if key == "ESSID"));
+
+        assert_eq!(Ok("foo \"bar\"".to_owned()), unquote("\"foo \\\"bar\\\"\""));
+        assert_eq!(Ok("foo\\".to_owned()), unquote("'foo\\'"));
+        assert_eq!(Ok("\"0123".to_owned()), unquote("\\\"0123"));
+        assert_eq!(Ok("foo".to_owned()), unquote("'foo' # don't"));
+        assert_eq!(Ok("foo".to_owned()), unquote("foo #comment"));
+        assert_eq!(Ok("#abc12345".to_owned()), unquote("#abc12345"));
+        assert_eq!(Ok("foo#bar".to_owned()), unquote("foo#bar"));
+        assert_eq!(Ok(String::new()), unquote(" #comment"));
+
+        let sample = b"Connection=wireless\nESSID=#hash_network\nSecurity=wpa\nKey=#abc12345\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!("#hash_network", network.ssid());
+        assert_eq!(&Security::PSK(PSKSecurity::Password("#abc12345".to_owned())), network.security());
+    }
+
+    #[test]
+    fn test_shell_unquote() {
+        // Only some characters can be escaped within double quotes.
+        assert_eq!(Ok("pass\\word".to_owned()), unquote("\"pass\\\\word\""));
+        assert_eq!(Ok("pass\\nword".to_owned()), unquote("\"pass\\nword\""));
+        assert_eq!(Ok("pass\"word".to_owned()), unquote("pass\\\"word"));
+        assert_eq!(Ok("pass\\word".to_owned()), unquote("pass\\\\word"));
+        assert_eq!(Ok("it's".to_owned()), unquote("\"it's\""));
+
+        // An escaped quote is a passphrase, a literal leading quote makes it a raw key.
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=\"foo\\\"bar\\\\baz\"\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("foo\"bar\\baz".to_owned())), network.security());
+
+        let sample = format!("Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey='\"{}'\n", "0123".repeat(16));
+        let network = parse_network(&mut sample.as_bytes()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::PSK("0123".repeat(16))), network.security());
+    }
+
+    #[test]
+    fn test_parse_wep() {
+        let sample = b"Connection=wireless\nESSID=old_network\nSecurity=wep\nKey=\\\"0123456789\n";
+        assert!(matches!(parse_networks(&mut sample.as_ref()), Err(ConversionError::UnsupportedWEP)));
+    }
+
+    #[test]
+    fn test_parse_eap() {
+        let sample = b"Description='University network'\n\
+            Interface=wlan0\n\
+            Connection=wireless\n\
+            Security=wpa-configsection\n\
+            IP=dhcp\n\
+            WPAConfigSection=(\n\
+            \x20   'ssid=\"eduroam\"'\n\
+            \x20   'proto=RSN'\n\
+            \x20   'key_mgmt=WPA-EAP'\n\
+            \x20   'eap=PEAP'\n\
+            \x20   # The outer identity is visible to anyone\n\
+            \x20   'anonymous_identity=\"anonymous@example.org\"'\n\
+            \x20   'identity=\"user@example.org\"'\n\
+            \x20   'password=\"foo password\"'\n\
+            \x20   'ca_cert=\"/etc/ssl/certs/ca.pem\"'\n\
+            \x20   'phase2=\"auth=MSCHAPV2\"'\n\
+            )\n";
+
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!("eduroam", network.ssid());
+        assert_eq!(Some("University network"), network.description());
+
+        let expected = EAPSecurity {
+            method: EAPMethod::PEAP,
+            identity: Some("user@example.org".to_owned()),
+            anonymous_identity: Some("anonymous@example.org".to_owned()),
+            password: Some("foo password".to_owned()),
+            phase2: Some("MSCHAPV2".to_owned()),
+            ca_cert: Some("/etc/ssl/certs/ca.pem".to_owned()),
+            client_cert: None,
+            client_key: None,
+            client_key_passphrase: None,
+        };
+        assert_eq!(&Security::EAP(expected), network.security());
+
+        assert_eq!("Tunneled-MSCHAPv2", phase2_method(EAPMethod::TTLS, "auth=MSCHAPV2").unwrap());
+
+        let sample = b"Connection=wireless\nSecurity=wpa-configsection\nWPAConfigSection=('ssid=656475726f616d' 'key_mgmt=WPA-PSK')\n";
+        assert!(matches!(parse_networks(&mut sample.as_ref()), Err(ConversionError::Unsupported)));
+    }
+
+    #[test]
+    fn test_unprefixed_raw_psk() {
+        let psk = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let sample = format!("Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey={}\n", psk);
+        let network = parse_network(&mut sample.as_bytes()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::PSK(psk.to_owned())), network.security());
+
+        // One character short, so it can only be a passphrase.
+        let sample = format!("Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey={}\n", &psk[1..]);
+        let network = parse_network(&mut sample.as_bytes()).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password(psk[1..].to_owned())), network.security());
+    }
+
+    #[test]
+    fn test_parse_sae() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=sae\nKey=foo_password\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&Security::SAE("foo_password".to_owned()), network.security());
+
+        let sample = b"Connection=wireless\nSecurity=wpa-configsection\n\
+            WPAConfigSection=('ssid=\"foo_network\"' 'key_mgmt=SAE' 'psk=\"foo_password\"' 'ieee80211w=2')\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!("foo_network", network.ssid());
+        assert_eq!(&Security::SAE("foo_password".to_owned()), network.security());
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert!(config.get_from(Some("Security"), "PreSharedKey").is_none());
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=sae\nKey=\\\"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::Unsupported)));
+    }
+
+    #[test]
+    fn test_parse_netmask() {
+        let cidr = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress='192.168.1.10/24'\n";
+        let netmask = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress='192.168.1.10'\nNetmask=255.255.255.0\n";
+
+        for sample in [cidr.as_ref(), netmask.as_ref()] {
+            let network = parse_network(&mut &sample[..]).unwrap();
+            let mut config = Ini::new();
+            network.write_config(&mut config, &WriteOptions::default());
+
+            assert_eq!(config.get_from(Some("IPv4"), "Address"), Some("192.168.1.10"));
+            assert_eq!(config.get_from(Some("IPv4"), "Netmask"), Some("255.255.255.0"));
+        }
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress='192.168.1.10'\nNetmask=255.0.255.0\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::InvalidIpConfig(_))));
+    }
+
+    #[test]
+    fn test_parse_dns_domain() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress='192.168.1.10/24'\n\
+            DNS=('192.168.1.1')\nDNSDomain='home.example'\nDNSSearch='home.example example.org'\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(vec![Warning::IgnoredDomains(vec!["example.org".to_owned()])], profile.warnings);
+
+        let mut config = Ini::new();
+        profile.networks[0].write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("IPv4"), "DomainName"), Some("home.example"));
+
+        // Without a domain, the first search domain is used.
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress='192.168.1.10/24'\nDNSSearch='example.org'\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert!(profile.warnings.is_empty());
+        match profile.networks[0].ipv4() {
+            IpConfig::Static(static_config) => assert_eq!(Some("example.org"), static_config.domain_name.as_deref()),
+            other => panic!("Expected a static configuration, got {:?}", other),
+        }
+
+        // DHCP provides its own.
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=dhcp\nDNSDomain='home.example'\n";
+        let profile = parse_profile(&mut sample.as_ref(), &ParseOptions::default()).unwrap();
+        assert_eq!(vec![Warning::IgnoredDomains(vec!["home.example".to_owned()])], profile.warnings);
+    }
+
+    #[test]
+    fn test_parse_dual_stack() {
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=static\nAddress='192.168.1.10/24'\n\
+            Gateway='192.168.1.1'\nDNS=('192.168.1.1')\nIP6=static\nAddress6='2001:db8::10/64'\n\
+            Gateway6='2001:db8::1'\nDNS6=('2001:db8::53')\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("IPv4"), "Address"), Some("192.168.1.10"));
+        assert_eq!(config.get_from(Some("IPv4"), "DNS"), Some("192.168.1.1"));
+        assert_eq!(config.get_from(Some("IPv6"), "Enabled"), Some("true"));
+        assert_eq!(config.get_from(Some("IPv6"), "Address"), Some("2001:db8::10/64"));
+        assert_eq!(config.get_from(Some("IPv6"), "Gateway"), Some("2001:db8::1"));
+        assert_eq!(config.get_from(Some("IPv6"), "DNS"), Some("2001:db8::53"));
+
+        let sample = b"Connection=wireless\nESSID=foo_network\nIP=dhcp\nIP6=stateless\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&IpConfig::Stateless, network.ipv6());
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert!(config.section(Some("IPv4")).is_none());
+        assert_eq!(config.get_from(Some("IPv6"), "Enabled"), Some("true"));
+        assert!(config.get_from(Some("IPv6"), "Address").is_none());
+    }
+
+    #[test]
+    fn test_mismatched_psk() {
+        let psk = hex::encode(compute_psk(b"foo_network", b"foo_password"));
+        let sample = |ssid: &str| format!("Connection=wireless\nSecurity=wpa-configsection\n\
+            WPAConfigSection=('ssid=\"{}\"' 'key_mgmt=WPA-PSK SAE' 'sae_password=\"foo_password\"' 'psk={}')\n", ssid, psk);
+
+        let profile = parse_profile(&mut sample("foo_network").as_bytes(), &ParseOptions::default()).unwrap();
+        assert!(profile.warnings.is_empty());
+
+        let profile = parse_profile(&mut sample("bar_network").as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(vec![Warning::MismatchedPSK("bar_network".to_owned())], profile.warnings);
+
+        let options = ParseOptions { strict: true, ..Default::default() };
+        let result = parse_profile(&mut sample("bar_network").as_bytes(), &options);
+        assert!(matches!(result, Err(ConversionError::Strict(Warning::MismatchedPSK(_)))));
+    }
+
+    #[test]
+    fn test_secrets() {
+        let secrets = vec![("foo_network".to_owned(), "foo_password".to_owned())].into_iter().collect();
+        let options = ParseOptions { secrets, ..Default::default() };
+        let expected = Security::PSK(PSKSecurity::Password("foo_password".to_owned()));
+
+        for sample in ["Connection=wireless\nESSID=foo_network\nSecurity=wpa\n", "Connection=wireless\nESSID='foo_network'\nSecurity=wpa\nKey=\n"] {
+            let profile = parse_profile(&mut sample.as_bytes(), &options).unwrap();
+            assert_eq!(&expected, profile.networks[0].security());
+        }
+
+        // The profile's own key takes precedence.
+        let sample = b"Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=bar_password\n";
+        let profile = parse_profile(&mut sample.as_ref(), &options).unwrap();
+        assert_eq!(&Security::PSK(PSKSecurity::Password("bar_password".to_owned())), profile.networks[0].security());
+
+        let sample = b"Connection=wireless\nESSID=bar_network\nSecurity=wpa\n";
+        assert!(matches!(parse_profile(&mut sample.as_ref(), &options), Err(ConversionError::MissingKeys)));
+    }
+
+    #[test]
+    fn test_bom_and_crlf() {
+        let expected = Network::new("foo_network".to_owned(), Security::PSK(PSKSecurity::Password("foo_password".to_owned())));
+        let samples: [&[u8]; 2] = [
+            b"\xef\xbb\xbfConnection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n",
+            b"Connection=wireless\r\nESSID=foo_network\r\nSecurity=wpa\r\nKey='foo_password'\r\n",
+        ];
+
+        for sample in &samples {
+            assert_eq!(expected, parse_network(&mut &sample[..]).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let sample = b"Connection=wireless\nESSID=caf\xe9\nSecurity=none\n";
+        let error = parse_network(&mut sample.as_ref()).unwrap_err();
+        assert!(matches!(error, ConversionError::InvalidUtf8));
+        assert!(error.to_string().contains("ESSID=\\\"<hex>"), "{}", error);
+    }
+
+    #[test]
+    fn test_hex_essid() {
+        let sample = b"Connection=wireless\nESSID=\\\"666f6f5f6e6574776f726b\nSecurity=wpa\nKey=bar_password\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!("foo_network", network.ssid());
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("Security"), "PreSharedKey"),
+                   Some("90b193aaec1446630aeb1d1c24191f580e03e3e4d592b5b682b157a04fa26956"));
+
+        let sample = b"Connection=wireless\nESSID='\"foo_network\"'\nSecurity=none\n";
+        assert_eq!("foo_network", parse_network(&mut sample.as_ref()).unwrap().ssid());
+
+        let sample = b"Connection=wireless\nESSID=\\\"not hex\nSecurity=none\n";
+        assert!(matches!(parse_network(&mut sample.as_ref()), Err(ConversionError::ParseError(..))));
+
+        // SSIDs are bytes, which need not be UTF-8.
+        let sample = b"Connection=wireless\nESSID=\\\"ff00\nSecurity=wpa\nKey=bar_password\n";
+        let network = parse_network(&mut sample.as_ref()).unwrap();
+        assert_eq!(&[0xff, 0x00], network.ssid().as_bytes());
+        assert_eq!("=ff00.psk", network.iwd_file_name(HexCase::Lower));
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("Security"), "PreSharedKey"),
+                   Some(hex::encode(compute_psk(&[0xff, 0x00], b"bar_password")).as_str()));
+        assert!(write_profile(&network, "wlan0").unwrap().contains("ESSID=\\\"ff00\n"));
+
+        let sample = b"Connection=wireless\nSecurity=wpa-configsection\nWPAConfigSection=('ssid=ff00' 'key_mgmt=SAE' 'sae_password=\"bar_password\"')\n";
+        assert_eq!(&[0xff, 0x00], parse_network(&mut sample.as_ref()).unwrap().ssid().as_bytes());
+    }
+}
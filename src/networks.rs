@@ -1,65 +1,732 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::str;
+use std::str::FromStr;
+
 use hmac::Hmac;
 use ini::Ini;
 use pbkdf2::pbkdf2;
 use sha1::Sha1;
 
-#[derive(Eq, PartialEq, Debug)]
+use crate::convert::ConversionError;
+use crate::ip::IpConfig;
+
+/// Letter case used for hex-encoded SSIDs and keys.
+///
+/// Standard iwd uses lowercase, but some builds expect uppercase.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum HexCase {
+    #[default]
+    Lower,
+    Upper,
+}
+
+impl HexCase {
+    /// Hex-encode the given bytes in this case.
+    pub fn encode(self, data: impl AsRef<[u8]>) -> String {
+        match self {
+            HexCase::Lower => hex::encode(data),
+            HexCase::Upper => hex::encode_upper(data),
+        }
+    }
+
+    /// Convert already hex-encoded data, like a raw PSK from a profile, to this case.
+    pub fn recase(self, hex: &str) -> String {
+        match self {
+            HexCase::Lower => hex.to_ascii_lowercase(),
+            HexCase::Upper => hex.to_ascii_uppercase(),
+        }
+    }
+}
+
+impl FromStr for HexCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lower" => Ok(HexCase::Lower),
+            "upper" => Ok(HexCase::Upper),
+            _ => Err(format!("Invalid hex case: {}", s)),
+        }
+    }
+}
+
+/// Which keys `Network::write_config` emits.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum ConfigFormat {
+    /// Everything that was converted, with the PSK precomputed as configured.
+    #[default]
+    Default,
+    /// Like `Default`, but never with a precomputed PSK next to the passphrase.
+    PassphraseOnly,
+    /// Only the passphrase or key and whether the network is hidden, leaving everything else to
+    /// the defaults of iwd.
+    Minimal,
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(ConfigFormat::Default),
+            "passphrase-only" => Ok(ConfigFormat::PassphraseOnly),
+            "minimal" => Ok(ConfigFormat::Minimal),
+            _ => Err(format!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+/// Number of PBKDF2 iterations used by WPA2 to derive the PSK.
+pub const PBKDF2_ITERATIONS: u32 = 4096;
+
+/// Options controlling how a network is written to an iwd configuration.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    pub hex_case: HexCase,
+    /// PBKDF2 iterations for precomputed PSKs. Anything other than `PBKDF2_ITERATIONS` produces
+    /// keys that standard WPA2 networks will not accept.
+    pub psk_iterations: u32,
+    /// Write the PSK derived from a passphrase, so iwd doesn't have to derive it when connecting.
+    pub precompute_psk: bool,
+    /// Translate the remaining wpa_supplicant options of the network where possible, and keep the
+    /// others as comments.
+    pub passthrough: bool,
+    /// Which of the converted keys are written.
+    pub format: ConfigFormat,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            hex_case: HexCase::default(),
+            psk_iterations: PBKDF2_ITERATIONS,
+            precompute_psk: true,
+            passthrough: false,
+            format: ConfigFormat::default(),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Eq, PartialEq, Clone)]
 pub enum PSKSecurity {
     Password(String),
     PSK(String),
 }
 
-#[derive(Eq, PartialEq, Debug)]
+/// Stands in for secrets in `Debug` output, which ends up in verbose output and logs.
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl fmt::Debug for PSKSecurity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PSKSecurity::Password(_) => f.debug_tuple("Password").field(&Redacted).finish(),
+            PSKSecurity::PSK(_) => f.debug_tuple("PSK").field(&Redacted).finish(),
+        }
+    }
+}
+
+/// Outer EAP method of an enterprise network.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum EAPMethod {
+    PEAP,
+    TTLS,
+    TLS,
+}
+
+impl EAPMethod {
+    /// Name of the method, as used in iwd settings.
+    pub fn name(self) -> &'static str {
+        match self {
+            EAPMethod::PEAP => "PEAP",
+            EAPMethod::TTLS => "TTLS",
+            EAPMethod::TLS => "TLS",
+        }
+    }
+}
+
+impl FromStr for EAPMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "PEAP" => Ok(EAPMethod::PEAP),
+            "TTLS" => Ok(EAPMethod::TTLS),
+            "TLS" => Ok(EAPMethod::TLS),
+            _ => Err(format!("Unsupported EAP method: {}", s)),
+        }
+    }
+}
+
+/// Settings of a WPA-Enterprise (802.1x) network.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Eq, PartialEq, Clone)]
+pub struct EAPSecurity {
+    pub method: EAPMethod,
+    pub identity: Option<String>,
+    /// Identity sent outside the tunnel, instead of the real one.
+    pub anonymous_identity: Option<String>,
+    pub password: Option<String>,
+    /// Inner method for PEAP and TTLS, as named by iwd, e.g. `MSCHAPV2` or `Tunneled-PAP`.
+    pub phase2: Option<String>,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub client_key_passphrase: Option<String>,
+}
+
+impl fmt::Debug for EAPSecurity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| Redacted);
+
+        f.debug_struct("EAPSecurity")
+            .field("method", &self.method)
+            .field("identity", &self.identity)
+            .field("anonymous_identity", &self.anonymous_identity)
+            .field("password", &redacted(&self.password))
+            .field("phase2", &self.phase2)
+            .field("ca_cert", &self.ca_cert)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("client_key_passphrase", &redacted(&self.client_key_passphrase))
+            .finish()
+    }
+}
+
+impl EAPSecurity {
+    /// The paths of the certificate and key files used by the network.
+    pub fn files_mut(&mut self) -> impl Iterator<Item=&mut String> {
+        vec![&mut self.ca_cert, &mut self.client_cert, &mut self.client_key].into_iter().flatten()
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Eq, PartialEq, Clone)]
 pub enum Security {
     Open,
     PSK(PSKSecurity),
+    /// WPA3-Personal, with its passphrase.
+    SAE(String),
+    EAP(EAPSecurity),
+}
+
+impl fmt::Debug for Security {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Security::Open => f.write_str("Open"),
+            Security::PSK(security) => f.debug_tuple("PSK").field(security).finish(),
+            Security::SAE(_) => f.debug_tuple("SAE").field(&Redacted).finish(),
+            Security::EAP(security) => f.debug_tuple("EAP").field(security).finish(),
+        }
+    }
 }
 
 impl Security {
-    fn get_extension(&self) -> &str {
+    /// Name of the iwd security type, as used in the file extension.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Security::Open => "open",
+            // iwd stores all personal networks alike.
+            Security::PSK(_) | Security::SAE(_) => "psk",
+            Security::EAP(_) => "8021x",
+        }
+    }
+
+    /// The extension of iwd files for this security type, including the dot.
+    pub fn get_extension(&self) -> String {
+        format!(".{}", self.type_name())
+    }
+}
+
+/// wpa_supplicant options that `write_config` translates with `passthrough`.
+const TRANSLATED_OPTIONS: &[&str] = &["disabled"];
+
+/// wpa_supplicant options that iwd always negotiates with the network itself.
+const NEGOTIATED_OPTIONS: &[&str] = &["proto", "pairwise", "group", "group_mgmt", "ieee80211w"];
+
+/// wpa_supplicant options that restrict which networks or servers are accepted, so that leaving
+/// them out weakens the security.
+const RESTRICTING_OPTIONS: &[&str] = &["ca_path", "subject_match", "altsubject_match", "domain_match", "domain_suffix_match"];
+
+/// A frequency band of 802.11.
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum Band {
+    Ghz2_4,
+    Ghz5,
+    Ghz6,
+}
+
+impl Band {
+    /// The band of a channel, given its center frequency in MHz.
+    pub fn from_frequency(mhz: u32) -> Option<Band> {
+        match mhz {
+            2401..=2495 => Some(Band::Ghz2_4),
+            5150..=5895 => Some(Band::Ghz5),
+            5925..=7125 => Some(Band::Ghz6),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Band {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Band::Ghz2_4 => write!(f, "2.4 GHz"),
+            Band::Ghz5 => write!(f, "5 GHz"),
+            Band::Ghz6 => write!(f, "6 GHz"),
+        }
+    }
+}
+
+/// The MAC address used when connecting to a network.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum MacAddress {
+    /// The permanent address of the interface, which iwd uses for known networks by default.
+    #[default]
+    Permanent,
+    /// A new random address for each connection.
+    Random,
+    Fixed([u8; 6]),
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         match self {
-            Security::Open => ".open",
-            Security::PSK(_) => ".psk",
+            MacAddress::Permanent => write!(f, "permanent"),
+            MacAddress::Random => write!(f, "random"),
+            MacAddress::Fixed(octets) => {
+                let octets: Vec<_> = octets.iter().map(|octet| format!("{:02x}", octet)).collect();
+                write!(f, "{}", octets.join(":"))
+            }
+        }
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = String;
+
+    /// Parse `permanent`, `random` or an address like `00:11:22:33:44:55`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "permanent" => return Ok(MacAddress::Permanent),
+            "random" => return Ok(MacAddress::Random),
+            _ => {}
+        }
+
+        let octets: Vec<_> = s.split(':')
+            .map(|octet| if octet.len() == 2 { u8::from_str_radix(octet, 16).ok() } else { None })
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Invalid MAC address: {}", s))?;
+        <[u8; 6]>::try_from(octets).map(MacAddress::Fixed).map_err(|_| format!("Invalid MAC address: {}", s))
+    }
+}
+
+/// The name of a network, which is up to 32 arbitrary bytes, though usually UTF-8 text.
+#[derive(Eq, PartialEq, Hash, Clone, Default)]
+pub struct Ssid(Vec<u8>);
+
+impl Ssid {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The SSID as text, if it is valid UTF-8.
+    pub fn to_str(&self) -> Option<&str> {
+        str::from_utf8(&self.0).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for Ssid {
+    fn from(ssid: Vec<u8>) -> Self {
+        Ssid(ssid)
+    }
+}
+
+impl From<String> for Ssid {
+    fn from(ssid: String) -> Self {
+        Ssid(ssid.into_bytes())
+    }
+}
+
+impl From<&str> for Ssid {
+    fn from(ssid: &str) -> Self {
+        Ssid(ssid.as_bytes().to_owned())
+    }
+}
+
+impl PartialEq<str> for Ssid {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Ssid {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<Ssid> for str {
+    fn eq(&self, other: &Ssid) -> bool {
+        self.as_bytes() == other.0
+    }
+}
+
+/// Shows invalid UTF-8 as replacement characters, so only for messages.
+impl Display for Ssid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl fmt::Debug for Ssid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.to_str() {
+            Some(ssid) => write!(f, "{:?}", ssid),
+            None => write!(f, "={}", hex::encode(&self.0)),
         }
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
 pub struct Network {
-    ssid: String,
+    ssid: Ssid,
     security: Security,
+    hidden: bool,
+    /// Whether iwd may connect to the network by itself.
+    auto_connect: bool,
+    ipv4: IpConfig,
+    ipv6: IpConfig,
+    description: Option<String>,
+    /// Name of the netctl profile the network was read from.
+    profile: Option<String>,
+    /// netctl-auto preference, higher is preferred.
+    priority: i32,
+    /// The only band the profile lets the network be used on, if any.
+    band: Option<Band>,
+    mac_address: MacAddress,
+    /// Options of a `WPAConfigSection` that the parser didn't interpret, in alphabetical order.
+    wpa_options: Vec<(String, String)>,
 }
 
+/// Builder for validated `Network` instances.
+#[derive(Default)]
+pub struct NetworkBuilder {
+    ssid: Option<Ssid>,
+    security: Option<Security>,
+    hidden: bool,
+    /// Connects automatically unless set otherwise.
+    auto_connect: Option<bool>,
+    ipv4: IpConfig,
+    ipv6: IpConfig,
+    description: Option<String>,
+    /// Name of the netctl profile the network was read from.
+    profile: Option<String>,
+    priority: i32,
+    band: Option<Band>,
+    mac_address: MacAddress,
+    /// Options of a `WPAConfigSection` that the parser didn't interpret, in alphabetical order.
+    wpa_options: Vec<(String, String)>,
+}
+
+impl NetworkBuilder {
+    pub fn new() -> NetworkBuilder {
+        NetworkBuilder::default()
+    }
+
+    pub fn ssid(mut self, ssid: impl Into<Ssid>) -> Self {
+        self.ssid = Some(ssid.into());
+        self
+    }
+
+    pub fn security(mut self, security: Security) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    pub fn auto_connect(mut self, auto_connect: bool) -> Self {
+        self.auto_connect = Some(auto_connect);
+        self
+    }
+
+    pub fn ipv4(mut self, ipv4: IpConfig) -> Self {
+        self.ipv4 = ipv4;
+        self
+    }
+
+    pub fn ipv6(mut self, ipv6: IpConfig) -> Self {
+        self.ipv6 = ipv6;
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn band(mut self, band: Option<Band>) -> Self {
+        self.band = band;
+        self
+    }
+
+    pub fn mac_address(mut self, mac_address: MacAddress) -> Self {
+        self.mac_address = mac_address;
+        self
+    }
+
+    pub fn wpa_options(mut self, wpa_options: Vec<(String, String)>) -> Self {
+        self.wpa_options = wpa_options;
+        self
+    }
+
+    /// Validate the collected fields and construct the network.
+    ///
+    /// The SSID must be present and non-empty, WPA passphrases must be 8 to 63 characters, and raw
+    /// PSKs 64 hexadecimal digits.
+    /// If no security was set, the network is assumed to be open.
+    pub fn build(self) -> Result<Network, ConversionError> {
+        let ssid = match self.ssid {
+            Some(ssid) if !ssid.is_empty() => ssid,
+            _ => return Err(ConversionError::MissingSSID),
+        };
+
+        let security = self.security.unwrap_or(Security::Open);
+        match &security {
+            Security::PSK(PSKSecurity::Password(passphrase)) if !(8..=63).contains(&passphrase.len()) => {
+                return Err(ConversionError::InvalidPassphrase);
+            }
+            Security::PSK(PSKSecurity::PSK(psk)) if psk.len() != 64 || !psk.chars().all(|c| c.is_ascii_hexdigit()) => {
+                return Err(ConversionError::InvalidPSK);
+            }
+            _ => {}
+        }
+
+        Ok(Network {
+            ssid,
+            security,
+            hidden: self.hidden,
+            auto_connect: self.auto_connect.unwrap_or(true),
+            ipv4: self.ipv4,
+            ipv6: self.ipv6,
+            description: self.description,
+            profile: self.profile,
+            priority: self.priority,
+            band: self.band,
+            mac_address: self.mac_address,
+            wpa_options: self.wpa_options,
+        })
+    }
+}
+
+/// Whether iwd keeps a character of an SSID as is in file names.
+///
+/// `=` is never safe, since it marks hex-encoded names: an SSID like `=cafe` must not be
+/// mistaken for the hex encoding of another SSID.
 fn is_safe_char(c: char) -> bool {
     c.is_ascii_alphanumeric() || "-_ ".contains(c)
 }
 
 impl Network {
-    pub fn new(ssid: String, security: Security) -> Network {
+    pub fn new(ssid: impl Into<Ssid>, security: Security) -> Network {
         Network {
-            ssid,
+            ssid: ssid.into(),
             security,
+            hidden: false,
+            auto_connect: true,
+            ipv4: IpConfig::default(),
+            ipv6: IpConfig::default(),
+            description: None,
+            profile: None,
+            priority: 0,
+            band: None,
+            mac_address: MacAddress::default(),
+            wpa_options: Vec::new(),
+        }
+    }
+
+    pub fn builder() -> NetworkBuilder {
+        NetworkBuilder::new()
+    }
+
+    pub fn ssid(&self) -> &Ssid {
+        &self.ssid
+    }
+
+    pub fn security(&self) -> &Security {
+        &self.security
+    }
+
+    pub fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn auto_connect(&self) -> bool {
+        self.auto_connect
+    }
+
+    pub fn ipv4(&self) -> &IpConfig {
+        &self.ipv4
+    }
+
+    pub fn ipv6(&self) -> &IpConfig {
+        &self.ipv6
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn band(&self) -> Option<Band> {
+        self.band
+    }
+
+    pub fn mac_address(&self) -> MacAddress {
+        self.mac_address
+    }
+
+    pub fn wpa_options(&self) -> &[(String, String)] {
+        &self.wpa_options
+    }
+
+    /// The wpa_supplicant options that `write_config` can't translate with `passthrough`, and why.
+    pub fn untranslated_options(&self) -> Vec<(&str, &str, &'static str)> {
+        self.wpa_options.iter()
+            .filter(|(key, _)| !TRANSLATED_OPTIONS.contains(&key.as_str()))
+            .map(|(key, value)| {
+                let reason = if NEGOTIATED_OPTIONS.contains(&key.as_str()) {
+                    "negotiated by iwd"
+                } else {
+                    "no iwd equivalent"
+                };
+                (key.as_str(), value.as_str(), reason)
+            })
+            .collect()
+    }
+
+    /// The ways in which the iwd configuration of this network accepts more than the profile it was
+    /// read from.
+    pub fn downgrades(&self) -> Vec<String> {
+        let mut downgrades = Vec::new();
+        // iwd has no way to insist on SAE, so it also connects when the network only offers WPA2.
+        if let Security::SAE(_) = self.security {
+            downgrades.push("WPA3 is required, but iwd also accepts WPA2".to_owned());
+        }
+
+        for (key, value) in &self.wpa_options {
+            if RESTRICTING_OPTIONS.contains(&key.as_str()) || (key == "ieee80211w" && value == "2") {
+                downgrades.push(format!("{}={} is not converted", key, value));
+            }
+        }
+
+        downgrades
+    }
+
+    /// Replace the security settings, e.g. to refer to relocated certificates.
+    pub fn with_security(mut self, security: Security) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Replace the SSID, leaving the passphrase that a PSK is derived from as is.
+    pub fn with_ssid(mut self, ssid: impl Into<Ssid>) -> Self {
+        self.ssid = ssid.into();
+        self
+    }
+
+    /// Set the name of the netctl profile the network was read from.
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// A human-readable name for the network, from its description and profile name.
+    ///
+    /// iwd only knows networks by SSID, so this is purely informational.
+    pub fn display_name(&self) -> Option<String> {
+        match (&self.description, &self.profile) {
+            (Some(description), Some(profile)) => Some(format!("{} (profile {})", description, profile)),
+            (Some(description), None) => Some(description.clone()),
+            (None, Some(profile)) => Some(format!("profile {}", profile)),
+            (None, None) => None,
         }
     }
 
     /// Compute the filename (not the dir) for this file.
     ///
-    /// This function is based on storage_get_network_file_path in the iwd source code.
-    pub fn iwd_file_name(&self) -> String {
-        let mut name = if self.ssid.chars().all(is_safe_char) {
-            self.ssid.clone()
-        } else {
-            let mut buf = String::from("=");
-            buf += &hex::encode(self.ssid.as_bytes());
-            buf
+    /// This function is based on storage_get_network_file_path in the iwd source code. SSIDs with
+    /// characters iwd doesn't allow in file names are hex-encoded behind a `=`.
+    ///
+    /// ```
+    /// use netctl2iwd::{HexCase, Network, Security};
+    ///
+    /// let network = Network::new("Leiden University".to_owned(), Security::Open);
+    /// assert_eq!("Leiden University.open", network.iwd_file_name(HexCase::Lower));
+    ///
+    /// let network = Network::new("Café".to_owned(), Security::Open);
+    /// assert_eq!("=436166c3a9.open", network.iwd_file_name(HexCase::Lower));
+    /// ```
+    pub fn iwd_file_name(&self, hex_case: HexCase) -> String {
+        let mut name = match self.ssid.to_str().filter(|ssid| ssid.chars().all(is_safe_char)) {
+            Some(ssid) => ssid.to_owned(),
+            None => {
+                let mut buf = String::from("=");
+                buf += &hex_case.encode(self.ssid.as_bytes());
+                buf
+            }
         };
 
-        name += self.security.get_extension();
+        name += &self.security.get_extension();
 
         name
     }
 
-    pub fn write_config(&self, config: &mut Ini) {
+    /// Write the iwd settings for this network.
+    ///
+    /// The priority is not written: iwd has no per-network priority, and prefers the networks
+    /// that were connected to most recently instead. Neither is the band, since iwd only has global
+    /// band preferences in its main.conf.
+    pub fn write_config(&self, config: &mut Ini, options: &WriteOptions) {
         match &self.security {
             Security::Open => {}
 
@@ -67,27 +734,168 @@ impl Network {
                 let mut section = config.with_section(Some("Security".to_owned()));
 
                 match &security {
-                    PSKSecurity::PSK(psk) => section.set("PreSharedKey", psk.to_owned()),
+                    PSKSecurity::PSK(psk) => section.set("PreSharedKey", options.hex_case.recase(psk)),
+                    PSKSecurity::Password(passphrase) if !options.precompute_psk || options.format != ConfigFormat::Default => {
+                        section.set("Passphrase", passphrase.to_owned())
+                    }
                     PSKSecurity::Password(passphrase) => {
-                        let psk = compute_psk(self.ssid.as_bytes(), passphrase.as_bytes());
+                        let psk = compute_psk_iterations(self.ssid.as_bytes(), passphrase.as_bytes(),
+                                                         options.psk_iterations);
                         section.set("Passphrase", passphrase.to_owned())
-                            .set("PreSharedKey", hex::encode(&psk))
+                            .set("PreSharedKey", options.hex_case.encode(psk))
                     }
                 };
             }
+
+            // SAE doesn't use the PBKDF2-derived PSK, so only the passphrase is written.
+            Security::SAE(passphrase) => {
+                config.set_to(Some("Security"), "Passphrase".to_owned(), passphrase.to_owned());
+            }
+
+            Security::EAP(security) => write_eap(config, security),
         };
+
+        if self.hidden {
+            config.set_to(Some("Settings"), "Hidden".to_owned(), "true".to_owned());
+        }
+
+        if options.format == ConfigFormat::Minimal {
+            return;
+        }
+
+        if !self.auto_connect {
+            config.set_to(Some("Settings"), "AutoConnect".to_owned(), "false".to_owned());
+        }
+
+        if options.passthrough {
+            for (key, value) in &self.wpa_options {
+                if key == "disabled" && value == "1" {
+                    config.set_to(Some("Settings"), "AutoConnect".to_owned(), "false".to_owned());
+                }
+            }
+        }
+
+        // The permanent address is the default, so it needs no setting.
+        match self.mac_address {
+            MacAddress::Permanent => {}
+            MacAddress::Random => config.set_to(Some("Settings"), "AlwaysRandomizeAddress".to_owned(), "true".to_owned()),
+            MacAddress::Fixed(_) => config.set_to(Some("Settings"), "AddressOverride".to_owned(), self.mac_address.to_string()),
+        }
+
+        write_ip_config(config, "IPv4", &self.ipv4);
+        write_ip_config(config, "IPv6", &self.ipv6);
+    }
+}
+
+/// Write the iwd `[Security]` settings of an enterprise network.
+///
+/// The tunneled methods send the anonymous identity, if any, outside the tunnel and the real
+/// identity inside it.
+fn write_eap(config: &mut Ini, security: &EAPSecurity) {
+    let method = security.method.name();
+    let mut set = |key: String, value: Option<&String>| {
+        if let Some(value) = value {
+            config.set_to(Some("Security"), key, value.clone());
+        }
+    };
+
+    set("EAP-Method".to_owned(), Some(&method.to_owned()));
+
+    match security.method {
+        EAPMethod::TLS => {
+            set("EAP-Identity".to_owned(), security.identity.as_ref());
+            set("EAP-TLS-CACert".to_owned(), security.ca_cert.as_ref());
+            set("EAP-TLS-ClientCert".to_owned(), security.client_cert.as_ref());
+            set("EAP-TLS-ClientKey".to_owned(), security.client_key.as_ref());
+            set("EAP-TLS-ClientKeyPassphrase".to_owned(), security.client_key_passphrase.as_ref());
+        }
+        EAPMethod::PEAP | EAPMethod::TTLS => {
+            let outer_identity = security.anonymous_identity.as_ref().or(security.identity.as_ref());
+            set("EAP-Identity".to_owned(), outer_identity);
+            set(format!("EAP-{}-CACert", method), security.ca_cert.as_ref());
+            set(format!("EAP-{}-Phase2-Method", method), security.phase2.as_ref());
+            set(format!("EAP-{}-Phase2-Identity", method), security.identity.as_ref());
+            set(format!("EAP-{}-Phase2-Password", method), security.password.as_ref());
+        }
+    }
+}
+
+/// Write the iwd section for the IP configuration of one address family.
+///
+/// Automatic configuration is iwd's default, so it needs no section. iwd supports a single static
+/// address; IPv4 addresses are written with a separate `Netmask`, IPv6 ones in CIDR notation.
+/// Static routes cannot be represented and are not written.
+fn write_ip_config(config: &mut Ini, section_name: &str, ip_config: &IpConfig) {
+    match ip_config {
+        IpConfig::Dhcp => {}
+        IpConfig::Stateless => {
+            // iwd configures IPv6 addresses from router advertisements once it's enabled.
+            if section_name == "IPv6" {
+                config.set_to(Some(section_name), "Enabled".to_owned(), "true".to_owned());
+            }
+        }
+        IpConfig::Disabled => {
+            // iwd has no way to disable IPv4 for a network.
+            if section_name == "IPv6" {
+                config.with_section(Some(section_name)).set("Enabled", "false");
+            }
+        }
+        IpConfig::Static(static_config) => {
+            if section_name == "IPv6" {
+                config.set_to(Some(section_name), "Enabled".to_owned(), "true".to_owned());
+            }
+
+            if let Some(address) = static_config.addresses.first() {
+                match address.netmask() {
+                    Some(netmask) => {
+                        config.set_to(Some(section_name), "Address".to_owned(), address.ip.to_string());
+                        config.set_to(Some(section_name), "Netmask".to_owned(), netmask.to_string());
+                    }
+                    None => config.set_to(Some(section_name), "Address".to_owned(), address.to_string()),
+                }
+            }
+
+            if let Some(gateway) = static_config.gateway {
+                config.set_to(Some(section_name), "Gateway".to_owned(), gateway.to_string());
+            }
+
+            if !static_config.dns.is_empty() {
+                let dns: Vec<_> = static_config.dns.iter().map(|d| d.to_string()).collect();
+                config.set_to(Some(section_name), "DNS".to_owned(), dns.join(" "));
+            }
+
+            if let (Some(domain_name), "IPv4") = (&static_config.domain_name, section_name) {
+                config.set_to(Some(section_name), "DomainName".to_owned(), domain_name.clone());
+            }
+        }
     }
 }
 
+/// Compute the WPA2 PSK for a passphrase, as iwd would: PBKDF2-HMAC-SHA1 with the SSID as the
+/// salt and 4096 iterations, giving a 256-bit key.
+///
+/// ```
+/// use netctl2iwd::compute_psk;
+///
+/// let psk = compute_psk(b"foo_network", b"bar_password");
+/// assert_eq!("90b193aaec1446630aeb1d1c24191f580e03e3e4d592b5b682b157a04fa26956", hex::encode(psk));
+/// ```
 pub fn compute_psk(ssid: &[u8], passphrase: &[u8]) -> [u8; 32] {
+    compute_psk_iterations(ssid, passphrase, PBKDF2_ITERATIONS)
+}
+
+/// Compute a PSK with a non-standard number of PBKDF2 iterations.
+pub fn compute_psk_iterations(ssid: &[u8], passphrase: &[u8], iterations: u32) -> [u8; 32] {
     let mut buffer = [0u8; 32];
 
-    pbkdf2::<Hmac<Sha1>>(passphrase, ssid, 4096, &mut buffer);
+    pbkdf2::<Hmac<Sha1>>(passphrase, ssid, iterations as usize, &mut buffer);
     buffer
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::ip::StaticConfig;
+
     use super::*;
 
     const FOO_PASSWORD: &str = "bar_password";
@@ -105,26 +913,296 @@ mod tests {
         assert_eq!(FOO_PSK, result_hex);
     }
 
+    #[test]
+    fn test_compute_psk_iterations() {
+        assert_eq!(compute_psk(b"foo_network", FOO_PASSWORD.as_bytes()),
+                   compute_psk_iterations(b"foo_network", FOO_PASSWORD.as_bytes(), PBKDF2_ITERATIONS));
+
+        let result = compute_psk_iterations(b"foo_network", FOO_PASSWORD.as_bytes(), 1000);
+        assert_ne!(FOO_PSK, hex::encode(result));
+        assert_eq!(result, compute_psk_iterations(b"foo_network", FOO_PASSWORD.as_bytes(), 1000));
+    }
+
     #[test]
     fn test_iwd_file_name() {
-        let network = Network {
-            ssid: "Leiden University".to_string(),
-            security: Security::Open,
-        };
-        assert_eq!("Leiden University.open", network.iwd_file_name());
-        assert_eq!("foo_network.psk", foo_network().iwd_file_name());
-        let network = Network {
-            ssid: "With illegal characters?".to_string(),
-            security: Security::Open,
-        };
-        assert_eq!("=5769746820696c6c6567616c20636861726163746572733f.open", network.iwd_file_name());
+        let network = Network::new("Leiden University".to_string(), Security::Open);
+        assert_eq!("Leiden University.open", network.iwd_file_name(HexCase::Lower));
+        assert_eq!("foo_network.psk", foo_network().iwd_file_name(HexCase::Lower));
+        let network = Network::new("With illegal characters?".to_string(), Security::Open);
+        assert_eq!("=5769746820696c6c6567616c20636861726163746572733f.open", network.iwd_file_name(HexCase::Lower));
+
+        // Otherwise it would be read back as the hex-encoded SSID "\xca\xfe".
+        let network = Network::new("=cafe".to_string(), Security::Open);
+        assert_eq!("=3d63616665.open", network.iwd_file_name(HexCase::Lower));
+        let network = Network::new("foo=bar".to_string(), Security::Open);
+        assert_eq!("=666f6f3d626172.open", network.iwd_file_name(HexCase::Lower));
     }
 
     #[test]
     fn test_write_config() {
         let mut config = Ini::new();
-        foo_network().write_config(&mut config);
+        foo_network().write_config(&mut config, &WriteOptions::default());
         assert_eq!(config.get_from(Some("Security"), "Passphrase"), Some(FOO_PASSWORD));
         assert_eq!(config.get_from(Some("Security"), "PreSharedKey"), Some(FOO_PSK));
     }
+
+    #[test]
+    fn test_hex_case() {
+        let network = Network::new("Café".to_owned(),
+                                   Security::PSK(PSKSecurity::Password(FOO_PASSWORD.to_owned())));
+        assert_eq!("=436166c3a9.psk", network.iwd_file_name(HexCase::Lower));
+        assert_eq!("=436166C3A9.psk", network.iwd_file_name(HexCase::Upper));
+
+        let psk = compute_psk("Café".as_bytes(), FOO_PASSWORD.as_bytes());
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("Security"), "PreSharedKey"), Some(hex::encode(psk).as_str()));
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions { hex_case: HexCase::Upper, ..WriteOptions::default() });
+        assert_eq!(config.get_from(Some("Security"), "PreSharedKey"), Some(hex::encode_upper(psk).as_str()));
+
+        // Raw keys from the profile are written in the same case.
+        let network = Network::new("foo_network", Security::PSK(PSKSecurity::PSK(hex::encode_upper(psk))));
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("Security"), "PreSharedKey"), Some(hex::encode(psk).as_str()));
+
+        let network = Network::new("foo_network", Security::PSK(PSKSecurity::PSK(hex::encode(psk))));
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions { hex_case: HexCase::Upper, ..WriteOptions::default() });
+        assert_eq!(config.get_from(Some("Security"), "PreSharedKey"), Some(hex::encode_upper(psk).as_str()));
+    }
+
+    #[test]
+    fn test_builder() {
+        let ipv4 = IpConfig::Static(StaticConfig {
+            addresses: vec!["192.168.1.10/24".parse().unwrap()],
+            gateway: Some([192, 168, 1, 1].into()),
+            dns: vec![[192, 168, 1, 1].into()],
+            routes: Vec::new(),
+            domain_name: None,
+        });
+
+        let network = Network::builder()
+            .ssid("foo_network")
+            .security(Security::PSK(PSKSecurity::Password(FOO_PASSWORD.to_owned())))
+            .hidden(true)
+            .ipv4(ipv4.clone())
+            .description("Foo's network")
+            .build()
+            .unwrap();
+
+        let expected = Network {
+            ssid: "foo_network".into(),
+            security: Security::PSK(PSKSecurity::Password(FOO_PASSWORD.to_owned())),
+            hidden: true,
+            auto_connect: true,
+            ipv4: ipv4.clone(),
+            ipv6: IpConfig::Dhcp,
+            description: Some("Foo's network".to_owned()),
+            profile: None,
+            priority: 0,
+            band: None,
+            mac_address: MacAddress::Permanent,
+            wpa_options: Vec::new(),
+        };
+
+        assert_eq!(expected, network);
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        assert!(matches!(Network::builder().build(), Err(ConversionError::MissingSSID)));
+        assert!(matches!(Network::builder().ssid("").build(), Err(ConversionError::MissingSSID)));
+
+        let short = Network::builder()
+            .ssid("foo_network")
+            .security(Security::PSK(PSKSecurity::Password("short".to_owned())))
+            .build();
+        assert!(matches!(short, Err(ConversionError::InvalidPassphrase)));
+    }
+
+    #[test]
+    fn test_write_ip_config() {
+        let network = Network::builder()
+            .ssid("foo_network")
+            .ipv4(IpConfig::Static(StaticConfig {
+                addresses: vec!["192.168.1.10/24".parse().unwrap()],
+                gateway: Some([192, 168, 1, 1].into()),
+                dns: vec![[192, 168, 1, 1].into(), [9, 9, 9, 9].into()],
+                routes: Vec::new(),
+                domain_name: None,
+            }))
+            .ipv6(IpConfig::Disabled)
+            .build()
+            .unwrap();
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(config.get_from(Some("IPv4"), "Address"), Some("192.168.1.10"));
+        assert_eq!(config.get_from(Some("IPv4"), "Netmask"), Some("255.255.255.0"));
+        assert_eq!(config.get_from(Some("IPv4"), "Gateway"), Some("192.168.1.1"));
+        assert_eq!(config.get_from(Some("IPv4"), "DNS"), Some("192.168.1.1 9.9.9.9"));
+        assert_eq!(config.get_from(Some("IPv6"), "Enabled"), Some("false"));
+    }
+
+    #[test]
+    fn test_no_precompute() {
+        let network = Network::new("foo_network".to_owned(), Security::PSK(PSKSecurity::Password(FOO_PASSWORD.to_owned())));
+        let options = WriteOptions { precompute_psk: false, ..WriteOptions::default() };
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &options);
+        let section = config.section(Some("Security")).unwrap();
+        assert_eq!(Some(FOO_PASSWORD), section.get("Passphrase").map(String::as_str));
+        assert!(section.get("PreSharedKey").is_none());
+    }
+
+    #[test]
+    fn test_downgrades() {
+        assert!(foo_network().downgrades().is_empty());
+
+        let network = Network::new("foo_network".to_owned(), Security::SAE(FOO_PASSWORD.to_owned()));
+        assert_eq!(1, network.downgrades().len());
+
+        let network = Network::builder()
+            .ssid("foo_network")
+            .security(Security::Open)
+            .wpa_options(vec![("ieee80211w".to_owned(), "2".to_owned()), ("ieee80211w".to_owned(), "1".to_owned()),
+                              ("domain_suffix_match".to_owned(), "example.com".to_owned())])
+            .build()
+            .unwrap();
+        assert_eq!(vec!["ieee80211w=2 is not converted", "domain_suffix_match=example.com is not converted"], network.downgrades());
+    }
+
+    #[test]
+    fn test_write_formats() {
+        let network = Network::builder()
+            .ssid("foo_network")
+            .security(Security::PSK(PSKSecurity::Password(FOO_PASSWORD.to_owned())))
+            .hidden(true)
+            .auto_connect(false)
+            .build()
+            .unwrap();
+        let write = |format| {
+            let mut config = Ini::new();
+            network.write_config(&mut config, &WriteOptions { format, ..WriteOptions::default() });
+            config
+        };
+
+        let config = write(ConfigFormat::Default);
+        assert_eq!(Some(FOO_PSK), config.get_from(Some("Security"), "PreSharedKey"));
+        assert_eq!(Some("false"), config.get_from(Some("Settings"), "AutoConnect"));
+
+        let config = write(ConfigFormat::PassphraseOnly);
+        assert_eq!(None, config.get_from(Some("Security"), "PreSharedKey"));
+        assert_eq!(Some("false"), config.get_from(Some("Settings"), "AutoConnect"));
+
+        let config = write(ConfigFormat::Minimal);
+        assert_eq!(Some(FOO_PASSWORD), config.get_from(Some("Security"), "Passphrase"));
+        assert_eq!(None, config.get_from(Some("Security"), "PreSharedKey"));
+        assert_eq!(Some("true"), config.get_from(Some("Settings"), "Hidden"));
+        assert_eq!(None, config.get_from(Some("Settings"), "AutoConnect"));
+    }
+
+    #[test]
+    fn test_write_sae() {
+        let network = Network::new("foo_network".to_owned(), Security::SAE(FOO_PASSWORD.to_owned()));
+        assert_eq!("foo_network.psk", network.iwd_file_name(HexCase::Lower));
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        let section = config.section(Some("Security")).unwrap();
+        assert_eq!(Some(FOO_PASSWORD), section.get("Passphrase").map(String::as_str));
+        assert!(section.get("PreSharedKey").is_none());
+    }
+
+    #[test]
+    fn test_write_mac_address() {
+        let mac_address: MacAddress = "00:11:22:AA:bb:cc".parse().unwrap();
+        let network = Network::builder().ssid("foo_network").mac_address(mac_address).build().unwrap();
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(Some("00:11:22:aa:bb:cc"), config.get_from(Some("Settings"), "AddressOverride"));
+
+        let network = Network::builder().ssid("foo_network").mac_address(MacAddress::Random).build().unwrap();
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(Some("true"), config.get_from(Some("Settings"), "AlwaysRandomizeAddress"));
+
+        assert!("00:11:22:33:44".parse::<MacAddress>().is_err());
+        assert!("00:11:22:33:44:5g".parse::<MacAddress>().is_err());
+    }
+
+    #[test]
+    fn test_write_auto_connect() {
+        let network = Network::builder().ssid("foo_network").build().unwrap();
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(None, config.get_from(Some("Settings"), "AutoConnect"));
+
+        let network = Network::builder().ssid("foo_network").auto_connect(false).build().unwrap();
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        assert_eq!(Some("false"), config.get_from(Some("Settings"), "AutoConnect"));
+    }
+
+    #[test]
+    fn test_write_eap() {
+        let security = EAPSecurity {
+            method: EAPMethod::PEAP,
+            identity: Some("user@example.org".to_owned()),
+            anonymous_identity: Some("anonymous@example.org".to_owned()),
+            password: Some("foo_password".to_owned()),
+            phase2: Some("MSCHAPV2".to_owned()),
+            ca_cert: Some("/etc/ssl/certs/ca.pem".to_owned()),
+            client_cert: None,
+            client_key: None,
+            client_key_passphrase: None,
+        };
+        let network = Network::new("eduroam".to_owned(), Security::EAP(security));
+        assert_eq!("eduroam.8021x", network.iwd_file_name(HexCase::Lower));
+
+        let mut config = Ini::new();
+        network.write_config(&mut config, &WriteOptions::default());
+        let section = config.section(Some("Security")).unwrap();
+        assert_eq!(Some("PEAP"), section.get("EAP-Method").map(String::as_str));
+        assert_eq!(Some("anonymous@example.org"), section.get("EAP-Identity").map(String::as_str));
+        assert_eq!(Some("/etc/ssl/certs/ca.pem"), section.get("EAP-PEAP-CACert").map(String::as_str));
+        assert_eq!(Some("MSCHAPV2"), section.get("EAP-PEAP-Phase2-Method").map(String::as_str));
+        assert_eq!(Some("user@example.org"), section.get("EAP-PEAP-Phase2-Identity").map(String::as_str));
+        assert_eq!(Some("foo_password"), section.get("EAP-PEAP-Phase2-Password").map(String::as_str));
+        assert_eq!(6, section.len());
+    }
+
+    #[test]
+    fn test_debug_redacted() {
+        let network = Network::new(
+            "foo".to_owned(),
+            Security::PSK(PSKSecurity::Password("foo_passphrase".to_owned())),
+        );
+        let debug = format!("{:?}", network);
+        assert!(debug.contains("Password(<redacted>)"));
+        assert!(!debug.contains("foo_passphrase"));
+
+        let security = EAPSecurity {
+            method: EAPMethod::TLS,
+            identity: Some("user@example.org".to_owned()),
+            anonymous_identity: None,
+            password: Some("foo_password".to_owned()),
+            phase2: None,
+            ca_cert: None,
+            client_cert: Some("/etc/ssl/user.pem".to_owned()),
+            client_key: Some("/etc/ssl/user.key".to_owned()),
+            client_key_passphrase: Some("foo_key_passphrase".to_owned()),
+        };
+        let debug = format!("{:?}", Security::EAP(security));
+        assert!(debug.contains("user@example.org"));
+        assert!(debug.contains("/etc/ssl/user.key"));
+        assert!(!debug.contains("foo_password"));
+        assert!(!debug.contains("foo_key_passphrase"));
+    }
 }
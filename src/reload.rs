@@ -0,0 +1,125 @@
+//! Make a running iwd pick up newly written networks.
+//!
+//! iwd watches its storage directory and loads new network files by itself, so this first asks it which networks it
+//! knows. iwd has no method to reread its storage, so the only way to load the missing ones is restarting the iwd
+//! service through systemd, which drops all of its connections.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use zbus::blocking::Connection;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::zvariant::OwnedValue;
+
+/// Well-known bus name of iwd.
+const IWD_BUS_NAME: &str = "net.connman.iwd";
+
+/// systemd unit running iwd.
+const IWD_UNIT: &str = "iwd.service";
+
+/// D-Bus interface of the networks iwd has loaded from its storage.
+const KNOWN_NETWORK_INTERFACE: &str = "net.connman.iwd.KnownNetwork";
+
+/// What to do when iwd hasn't picked up all networks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReloadMode {
+    /// Only report the networks iwd doesn't know.
+    Check,
+    /// Restart iwd, dropping its connections.
+    Restart,
+}
+
+/// The outcome of `reload_iwd`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Reloaded {
+    /// iwd already knows all networks.
+    Known,
+    /// iwd doesn't know these networks, and wasn't restarted.
+    Missing(Vec<String>),
+    Restarted,
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    /// iwd is not running, so there is nothing to reload.
+    NotRunning,
+    DBus(zbus::Error),
+}
+
+impl Display for ReloadError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ReloadError::NotRunning => write!(f, "iwd is not running; the networks will be loaded when it starts"),
+            ReloadError::DBus(e) => write!(f, "D-Bus error: {}", e),
+        }
+    }
+}
+
+impl From<zbus::Error> for ReloadError {
+    fn from(e: zbus::Error) -> Self {
+        ReloadError::DBus(e)
+    }
+}
+
+/// Make sure a running iwd knows the networks with the given SSIDs, restarting it with `ReloadMode::Restart` if it
+/// doesn't.
+pub fn reload_iwd(ssids: &[String], mode: ReloadMode) -> Result<Reloaded, ReloadError> {
+    let connection = Connection::system()?;
+
+    let running: bool = connection.call_method(
+        Some("org.freedesktop.DBus"),
+        "/org/freedesktop/DBus",
+        Some("org.freedesktop.DBus"),
+        "NameHasOwner",
+        &(IWD_BUS_NAME),
+    )?.body().deserialize()?;
+
+    if !running {
+        return Err(ReloadError::NotRunning);
+    }
+
+    let known = known_networks(&connection)?;
+    let missing: Vec<String> = ssids.iter()
+        .filter(|ssid| !known.contains(ssid))
+        .cloned()
+        .collect();
+
+    if missing.is_empty() {
+        return Ok(Reloaded::Known);
+    }
+
+    if mode == ReloadMode::Check {
+        return Ok(Reloaded::Missing(missing));
+    }
+
+    connection.call_method(
+        Some("org.freedesktop.systemd1"),
+        "/org/freedesktop/systemd1",
+        Some("org.freedesktop.systemd1.Manager"),
+        "RestartUnit",
+        &(IWD_UNIT, "replace"),
+    )?;
+
+    Ok(Reloaded::Restarted)
+}
+
+/// Names of the networks iwd has loaded.
+fn known_networks(connection: &Connection) -> Result<Vec<String>, ReloadError> {
+    let objects: HashMap<OwnedObjectPath, HashMap<String, HashMap<String, OwnedValue>>> = connection.call_method(
+        Some(IWD_BUS_NAME),
+        "/",
+        Some("org.freedesktop.DBus.ObjectManager"),
+        "GetManagedObjects",
+        &(),
+    )?.body().deserialize()?;
+
+    Ok(objects.values()
+        .filter_map(|interfaces| interfaces.get(KNOWN_NETWORK_INTERFACE))
+        .filter_map(|properties| properties.get("Name"))
+        .filter_map(|name| <&str>::try_from(&**name).ok())
+        .map(str::to_owned)
+        .collect())
+}
@@ -0,0 +1,206 @@
+//! A simple self-describing stream of iwd files.
+//!
+//! Each entry consists of a header line followed by the raw file contents:
+//!
+//! ```text
+//! netctl2iwd-file <mode in octal, at most 755> <length in bytes> <relative path>\n
+//! <contents>
+//! ```
+//!
+//! This allows converting locally and materializing the files elsewhere, e.g.
+//! `netctl2iwd -o - ... | ssh host 'netctl2iwd --unpack'`.
+
+use std::fs::DirBuilder;
+use std::fs::OpenOptions;
+use std::fs::Permissions;
+use std::io;
+use std::io::BufRead;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Marker starting every entry header.
+const MAGIC: &str = "netctl2iwd-file";
+
+/// Write a single file entry to the stream.
+pub fn write_entry(writer: &mut impl Write, name: &str, mode: u32, contents: &[u8]) -> io::Result<()> {
+    check_name(name)?;
+    writeln!(writer, "{} {:o} {} {}", MAGIC, mode, contents.len(), name)?;
+    writer.write_all(contents)
+}
+
+/// Read a single file entry from the stream.
+///
+/// # Return value
+///
+/// The name, mode and contents of the entry, or None at the end of the stream.
+pub fn read_entry(reader: &mut impl BufRead) -> io::Result<Option<(String, u32, Vec<u8>)>> {
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+        return Ok(None);
+    }
+
+    let header = header.strip_suffix('\n').ok_or_else(|| invalid("truncated header"))?;
+    let mut parts = header.splitn(4, ' ');
+
+    if parts.next() != Some(MAGIC) {
+        return Err(invalid("not a netctl2iwd stream"));
+    }
+
+    // Only plain permissions, and never writable by others, so a stream can't create setuid or
+    // world-writable files.
+    let mode = parts.next()
+        .and_then(|m| u32::from_str_radix(m, 8).ok())
+        .filter(|mode| mode & !0o755 == 0)
+        .ok_or_else(|| invalid("invalid mode"))?;
+    let length = parts.next()
+        .and_then(|l| l.parse::<usize>().ok())
+        .ok_or_else(|| invalid("invalid length"))?;
+    let name = parts.next().ok_or_else(|| invalid("missing file name"))?;
+    check_name(name)?;
+
+    // The length isn't trusted, so only as much is allocated as the stream really contains.
+    let mut contents = Vec::new();
+    reader.take(length as u64).read_to_end(&mut contents)?;
+    if contents.len() != length {
+        return Err(invalid("truncated contents"));
+    }
+
+    Ok(Some((name.to_owned(), mode, contents)))
+}
+
+/// Materialize all entries of a stream into a directory.
+///
+/// Missing subdirectories are created, but existing files are never overwritten.
+///
+/// # Return value
+///
+/// The paths of all files that were written.
+pub fn unpack(reader: &mut impl BufRead, output_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+
+    while let Some((name, mode, contents)) = read_entry(reader)? {
+        let path = output_dir.join(name);
+        // Checked again on the path itself, whatever `check_name` let through.
+        let relative = path.strip_prefix(output_dir).map_err(|_| invalid("unsafe file name"))?;
+        if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(invalid("unsafe file name"));
+        }
+        if let Some(parent) = path.parent() {
+            DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+        }
+
+        let mut output = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&path)?;
+        output.set_permissions(Permissions::from_mode(mode))?;
+        output.write_all(&contents)?;
+
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Make sure a relative path cannot escape the output directory or break the header.
+fn check_name(name: &str) -> io::Result<()> {
+    let unsafe_component = name.split('/').any(|c| c.is_empty() || c == "." || c == "..");
+
+    if unsafe_component || name.contains(['\n', '\0']) {
+        Err(invalid("unsafe file name"))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ini::Ini;
+
+    use crate::networks::HexCase;
+    use crate::networks::Network;
+    use crate::networks::PSKSecurity;
+    use crate::networks::Security;
+    use crate::networks::WriteOptions;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let networks = vec![
+            Network::new("Leiden University".to_owned(), Security::Open),
+            Network::new("foo_network".to_owned(), Security::PSK(PSKSecurity::Password("bar_password".to_owned()))),
+        ];
+
+        let mut stream = Vec::new();
+        let mut expected = Vec::new();
+        for network in &networks {
+            let mut config = Ini::new();
+            network.write_config(&mut config, &WriteOptions::default());
+            let mut contents = Vec::new();
+            config.write_to(&mut contents).unwrap();
+
+            let name = network.iwd_file_name(HexCase::Lower);
+            write_entry(&mut stream, &name, 0o600, &contents).unwrap();
+            expected.push((name, contents));
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let written = unpack(&mut stream.as_slice(), dir.path()).unwrap();
+        assert_eq!(expected.len(), written.len());
+
+        for (name, contents) in expected {
+            let path = dir.path().join(name);
+            assert_eq!(contents, fs::read(&path).unwrap());
+            assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+        }
+    }
+
+    #[test]
+    fn test_unsafe_names() {
+        let mut stream = b"netctl2iwd-file 600 0 ../escape.psk\n".as_ref();
+        assert!(read_entry(&mut stream).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        for mut stream in [&b"netctl2iwd-file 600 0 /etc/b.psk\n"[..], b"netctl2iwd-file 600 0 a/../b.psk\n",
+                           b"netctl2iwd-file 600 0 ./b.psk\n"] {
+            assert!(unpack(&mut stream, dir.path()).is_err());
+        }
+
+        // Setuid or world-writable files are refused, but the usual permissions are fine.
+        for mode in &["4777", "666", "10600"] {
+            let stream = format!("netctl2iwd-file {} 3 b.psk\nfoo", mode);
+            assert_eq!("invalid mode", unpack(&mut stream.as_bytes(), dir.path()).unwrap_err().to_string());
+        }
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+        unpack(&mut b"netctl2iwd-file 640 3 b.psk\nfoo".as_ref(), dir.path()).unwrap();
+        assert_eq!(0o640, fs::metadata(dir.path().join("b.psk")).unwrap().permissions().mode() & 0o7777);
+
+        assert!(write_entry(&mut Vec::new(), "/etc/b.psk", 0o600, b"").is_err());
+        assert!(write_entry(&mut Vec::new(), "secrets/b.psk", 0o600, b"").is_ok());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let mut stream = b"netctl2iwd-file 600 18446744073709551615 a.psk\n".as_ref();
+        assert_eq!(ErrorKind::InvalidData, read_entry(&mut stream).unwrap_err().kind());
+
+        let mut stream = b"netctl2iwd-file 600 10 a.psk\nshort".as_ref();
+        let error = read_entry(&mut stream).unwrap_err();
+        assert_eq!("truncated contents", error.to_string());
+    }
+}
@@ -0,0 +1,859 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+const OPEN_PROFILE: &str = "Connection=wireless\nESSID=open_network\nSecurity=none\n";
+const WIRED_PROFILE: &str = "Connection=ethernet\nInterface=eth0\n";
+
+fn netctl2iwd() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_netctl2iwd"))
+}
+
+fn write_profile(dir: &Path, name: &str, contents: &str) {
+    fs::write(dir.join(name), contents).unwrap();
+}
+
+#[test]
+fn test_exit_success() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    let status = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(0), status.code());
+}
+
+#[test]
+fn test_exit_partial_failure() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+
+    let status = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(1), status.code());
+}
+
+#[test]
+fn test_default_netctl_dir() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    let result = netctl2iwd()
+        .arg("--netctl-dir").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--yes")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    assert!(output.path().join("open_network.open").exists());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains(&format!("Scanning {} for netctl profiles", input.path().display())));
+}
+
+#[test]
+fn test_default_netctl_dir_declined() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    let mut child = netctl2iwd()
+        .arg("--netctl-dir").arg(input.path())
+        .arg("-o").arg(output.path())
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"n\n").unwrap();
+
+    assert_eq!(Some(4), child.wait().unwrap().code());
+    assert!(!output.path().join("open_network.open").exists());
+}
+
+#[test]
+fn test_exit_bad_arguments() {
+    let status = netctl2iwd()
+        .arg("--no-such-option")
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(2), status.code());
+}
+
+#[test]
+fn test_exit_missing_dir() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+
+    let status = netctl2iwd()
+        .arg("-i").arg(input.path().join("missing"))
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(3), status.code());
+}
+
+#[test]
+fn test_exit_nothing_to_do() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+
+    let status = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(4), status.code());
+}
+
+#[test]
+fn test_output_lock() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    // Hold the lock as if another run were busy with the output directory.
+    let lock = fs::File::create(output.path().join(".netctl2iwd.lock")).unwrap();
+    lock.lock().unwrap();
+
+    let mut child = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(child.try_wait().unwrap().is_none(), "run did not wait for the lock");
+    assert!(!output.path().join("open_network.open").exists());
+
+    drop(lock);
+    assert_eq!(Some(0), child.wait().unwrap().code());
+    assert!(output.path().join("open_network.open").exists());
+
+    // With --no-lock, the run doesn't wait for anyone.
+    let lock = fs::File::open(output.path().join(".netctl2iwd.lock")).unwrap();
+    lock.lock().unwrap();
+    fs::remove_file(output.path().join("open_network.open")).unwrap();
+
+    let status = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--no-lock")
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(Some(0), status.code());
+}
+
+#[test]
+fn test_dry_run() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "psk", "Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--dry-run")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains(&format!("Would write {}:", output.path().join("foo_network.psk").display())));
+    assert!(stdout.contains("Passphrase=foo_password"));
+    assert_eq!(0, fs::read_dir(output.path()).unwrap().count());
+}
+
+#[test]
+fn test_stdin_input() {
+    let output = tempfile::tempdir().unwrap();
+
+    let mut child = netctl2iwd()
+        .arg("-")
+        .arg("-o").arg(output.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(OPEN_PROFILE.as_bytes()).unwrap();
+
+    assert_eq!(Some(0), child.wait().unwrap().code());
+    assert!(output.path().join("open_network.open").exists());
+}
+
+#[test]
+fn test_stdout() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "psk", "Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n");
+
+    let result = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg(input.path().join("psk"))
+        .arg("-o").arg(output.path())
+        .arg("--stdout")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("# open_network.open from {}\n", input.path().join("open").display())));
+    assert!(stdout.contains(&format!("# foo_network.psk from {}\n[Security]", input.path().join("psk").display())));
+    assert_eq!(0, fs::read_dir(output.path()).unwrap().count());
+}
+
+#[test]
+fn test_quiet_summary() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "psk", "Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n");
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+    write_profile(output.path(), "foo_network.psk", "");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--quiet")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert_eq!("1 converted, 1 skipped (already exist), 1 failed\n", stdout);
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(!stderr.contains("Successfully converted"));
+    assert!(stderr.contains(&format!("Skipped {}", input.path().join("psk").display())));
+    assert!(stderr.contains(&format!("Failed to convert {}", input.path().join("wired").display())));
+
+    let result = netctl2iwd().arg("--quiet").arg("-v").arg(input.path().join("open")).output().unwrap();
+    assert_eq!(Some(2), result.status.code());
+    assert!(String::from_utf8(result.stderr).unwrap().contains("cannot be used with"));
+}
+
+#[test]
+fn test_json_format() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+
+    let result = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg(input.path().join("wired"))
+        .arg("-o").arg(output.path())
+        .arg("--format").arg("json")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), result.status.code());
+    let records: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+    assert_eq!(serde_json::json!([
+        {
+            "source": input.path().join("open").display().to_string(),
+            "file": "open_network.open",
+            "ssid": "open_network",
+            "security": "open",
+            "success": true,
+            "error": null,
+        },
+        {
+            "source": input.path().join("wired").display().to_string(),
+            "file": null,
+            "ssid": null,
+            "security": null,
+            "success": false,
+            "error": "Wired (ethernet) profile, which iwd doesn't manage; keep it in netctl, or move it to systemd-networkd",
+        },
+    ]), records);
+}
+
+#[test]
+fn test_path_expansion() {
+    let home = tempfile::tempdir().unwrap();
+    write_profile(home.path(), "open", OPEN_PROFILE);
+    fs::create_dir(home.path().join("iwd")).unwrap();
+
+    let status = netctl2iwd()
+        .arg("~/open")
+        .arg("--output-dir=~/iwd")
+        .env("HOME", home.path())
+        .status()
+        .unwrap();
+    assert_eq!(Some(0), status.code());
+    assert!(home.path().join("iwd/open_network.open").exists());
+
+    let result = netctl2iwd()
+        .arg("-i").arg("$NETCTL2IWD_UNDEFINED")
+        .env_remove("NETCTL2IWD_UNDEFINED")
+        .output()
+        .unwrap();
+    assert_eq!(Some(2), result.status.code());
+    assert!(String::from_utf8(result.stderr).unwrap().contains("NETCTL2IWD_UNDEFINED is not set"));
+}
+
+#[test]
+fn test_interactive_without_terminal() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    fs::write(output.path().join("open_network.open"), "[Settings]\nAutoConnect=false\n").unwrap();
+
+    // A piped stdin is not a terminal, so nothing is asked and nothing is overwritten.
+    let mut child = netctl2iwd()
+        .arg("--interactive")
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"y\n").unwrap();
+
+    assert_eq!(Some(1), child.wait().unwrap().code());
+    let contents = fs::read_to_string(output.path().join("open_network.open")).unwrap();
+    assert_eq!("[Settings]\nAutoConnect=false\n", contents);
+}
+
+#[test]
+fn test_verbose() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "psk", "Connection=wireless\nESSID=foo_network\nSecurity=wpa\nKey=foo_password\n");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("-v")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("Read keys: Connection, ESSID, Key, Security"));
+    assert!(stderr.contains("foo_network is written to foo_network.psk"));
+    assert!(!stderr.contains("foo_password"));
+
+    // The parsed network, including its secrets, is only shown at the second level.
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("-vv")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(result.stderr).unwrap().contains("Parsed Network"));
+}
+
+#[test]
+fn test_reverse() {
+    let input = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "foo_network.psk", "[Security]\nPassphrase=foo_password\n");
+
+    let result = netctl2iwd()
+        .arg("--reverse")
+        .arg("--interface").arg("wlp2s0")
+        .arg(input.path().join("foo_network.psk"))
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains("Interface='wlp2s0'\nConnection=wireless\nSecurity=wpa\nKey='foo_password'\nESSID='foo_network'\n"));
+}
+
+#[test]
+fn test_color() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    // Output to a pipe stays plain by default.
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+    assert!(!String::from_utf8(result.stdout).unwrap().contains('\x1b'));
+
+    fs::remove_file(output.path().join("open_network.open")).unwrap();
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--color").arg("always")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(result.stdout).unwrap().contains("\x1b[32mSuccessfully converted"));
+}
+
+#[test]
+fn test_input_tar() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let archive = input.path().join("netctl.tar");
+
+    let mut builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+    for (name, contents) in [("open", OPEN_PROFILE), ("wired", WIRED_PROFILE)] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+    }
+    builder.finish().unwrap();
+
+    let result = netctl2iwd()
+        .arg("--input-tar").arg(&archive)
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), result.status.code());
+    assert!(output.path().join("open_network.open").exists());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains(&format!("{}:open", archive.display())), "{}", stdout);
+
+    let status = netctl2iwd()
+        .arg("--input-tar").arg(input.path().join("missing.tar"))
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+    assert_eq!(Some(3), status.code());
+}
+
+#[test]
+fn test_list() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+
+    let result = netctl2iwd()
+        .arg("--list")
+        .arg(input.path().join("open"))
+        .arg(input.path().join("wired"))
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().map(|line| line.split_whitespace().collect::<Vec<_>>()).collect();
+    assert_eq!(vec!["PROFILE", "INTERFACE", "SSID", "SECURITY", "FILE"], lines[0]);
+    assert_eq!(&["-", "open_network", "open", "open_network.open"], &lines[1][1..]);
+    assert!(stdout.lines().nth(2).unwrap().ends_with("error: Wired (ethernet) profile, which iwd doesn't manage; keep it in netctl, or move it to systemd-networkd"), "{}", stdout);
+    assert_eq!(0, fs::read_dir(output.path()).unwrap().count());
+}
+
+#[test]
+fn test_output_name() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "other", OPEN_PROFILE);
+
+    let status = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg("--output-name").arg("home")
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+    assert_eq!(Some(0), status.code());
+    assert!(output.path().join("home.open").exists());
+
+    let result = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg(input.path().join("other"))
+        .arg("--output-name").arg("home")
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+    assert_eq!(Some(2), result.status.code());
+    assert!(String::from_utf8(result.stderr).unwrap().contains("--output-name needs exactly one input profile"));
+}
+
+#[test]
+fn test_progress_without_terminal() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    let result = netctl2iwd()
+        .arg("--progress")
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    assert!(!String::from_utf8(result.stderr).unwrap().contains("Processed"));
+}
+
+#[test]
+fn test_interface_filter() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "home", "Interface=wlan0\nConnection=wireless\nESSID=home\nSecurity=none\n");
+    write_profile(input.path(), "work", "Interface=wlan1\nConnection=wireless\nESSID=work\nSecurity=none\n");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--interface").arg("wlan0")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    assert!(output.path().join("home.open").exists());
+    assert!(!output.path().join("work.open").exists());
+    assert!(String::from_utf8(result.stdout).unwrap().contains("1 converted"));
+}
+
+#[test]
+fn test_check() {
+    let input = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+
+    let result = netctl2iwd().arg("--check").arg("-i").arg(input.path()).output().unwrap();
+    assert_eq!(Some(0), result.status.code());
+    assert!(String::from_utf8(result.stdout).unwrap().contains("0 problems in 1 profiles"));
+
+    write_profile(input.path(), "hidden", &format!("{}Hidden=yes\n", OPEN_PROFILE));
+    write_profile(input.path(), "wep", "Connection=wireless\nESSID=old_network\nSecurity=wep\nKey=s:abcde\n");
+
+    let output = tempfile::tempdir().unwrap();
+    let result = netctl2iwd()
+        .arg("--check")
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains("wep: error: WEP is not supported"), "{}", stdout);
+    assert!(stdout.contains("so only one of them can be written"), "{}", stdout);
+    assert!(stdout.contains("2 problems in 3 profiles"), "{}", stdout);
+    assert_eq!(0, fs::read_dir(output.path()).unwrap().count());
+}
+
+#[test]
+fn test_secrets() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "home", "Connection=wireless\nESSID=home_network\nSecurity=wpa\n");
+    write_profile(input.path(), "work", "Connection=wireless\nESSID=work_network\nSecurity=wpa\n");
+    // Taken literally, unlike in ini files.
+    write_profile(input.path(), "secrets.ini", "# Passphrases\nhome_network = home_password\nwork_network = it's a \\secret; #1\n");
+
+    let status = netctl2iwd()
+        .arg(input.path().join("home"))
+        .arg(input.path().join("work"))
+        .arg("--secrets").arg(input.path().join("secrets.ini"))
+        .arg("--no-precompute")
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(0), status.code());
+    let path = output.path().join("home_network.psk");
+    assert!(fs::read_to_string(&path).unwrap().contains("Passphrase=home_password\n"));
+    assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    let work = fs::read_to_string(output.path().join("work_network.psk")).unwrap();
+    // iwd unescapes the backslash again.
+    assert!(work.contains("Passphrase=it's a \\\\secret; #1\n"), "{}", work);
+
+    fs::write(input.path().join("secrets.ini"), "home_network\n").unwrap();
+    let result = netctl2iwd()
+        .arg(input.path().join("home"))
+        .arg("--secrets").arg(input.path().join("secrets.ini"))
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+    assert_eq!(Some(2), result.status.code());
+    assert!(String::from_utf8(result.stderr).unwrap().contains("line 1: expected key = value"));
+}
+
+#[test]
+fn test_mkdir() {
+    let input = tempfile::tempdir().unwrap();
+    let parent = tempfile::tempdir().unwrap();
+    let output = parent.path().join("iwd");
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(&output)
+        .output()
+        .unwrap();
+    assert_eq!(Some(2), result.status.code());
+    assert!(String::from_utf8(result.stderr).unwrap().contains("does not exist; create it or pass --mkdir"));
+    assert!(!output.exists());
+
+    let status = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(&output)
+        .arg("--mkdir")
+        .status()
+        .unwrap();
+    assert_eq!(Some(0), status.code());
+    assert!(output.join("open_network.open").exists());
+}
+
+#[test]
+fn test_subcommands() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+
+    let result = netctl2iwd()
+        .arg("list")
+        .arg(input.path().join("open"))
+        .output()
+        .unwrap();
+    assert_eq!(Some(0), result.status.code());
+    assert!(String::from_utf8(result.stdout).unwrap().contains("open_network.open"));
+
+    let result = netctl2iwd()
+        .arg("check")
+        .arg("-i").arg(input.path())
+        .output()
+        .unwrap();
+    assert_eq!(Some(0), result.status.code());
+    assert!(String::from_utf8(result.stdout).unwrap().contains("0 problems in 1 profiles"));
+
+    // Only convert writes files.
+    let status = netctl2iwd()
+        .arg("check")
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert_eq!(Some(2), status.code());
+
+    let status = netctl2iwd()
+        .arg("convert")
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .status()
+        .unwrap();
+    assert_eq!(Some(0), status.code());
+    assert!(output.path().join("open_network.open").exists());
+}
+
+#[test]
+fn test_profile_named_like_subcommand() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "list", OPEN_PROFILE);
+    write_profile(input.path(), "lists", WIRED_PROFILE);
+
+    for profile in &["lists", "./list"] {
+        let status = netctl2iwd()
+            .current_dir(input.path())
+            .arg(profile)
+            .arg("-o").arg(output.path())
+            .arg("--dry-run")
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert_ne!(Some(2), status.code(), "{} was taken for a subcommand", profile);
+    }
+
+    let status = netctl2iwd()
+        .current_dir(input.path())
+        .arg("-o").arg(output.path())
+        .arg("list")
+        .status()
+        .unwrap();
+    assert_eq!(Some(0), status.code());
+    assert!(output.path().join("open_network.open").exists());
+}
+
+#[test]
+fn test_exclude() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "open.bak", "Connection=wireless\nESSID=old_network\nSecurity=none\n");
+    write_profile(input.path(), "template", "Connection=wireless\nESSID=template\nSecurity=none\n");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--exclude").arg("*.bak")
+        .arg("--exclude").arg("templ*")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    assert!(String::from_utf8(result.stdout).unwrap().contains("1 converted, 0 skipped (already exist), 0 failed, 2 excluded"));
+    assert!(output.path().join("open_network.open").exists());
+    assert!(!output.path().join("old_network.open").exists());
+}
+
+#[test]
+fn test_multiple_input_dirs() {
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(first.path(), "open", OPEN_PROFILE);
+    write_profile(second.path(), "open-copy", OPEN_PROFILE);
+    write_profile(second.path(), "other", "Connection=wireless\nESSID=other_network\nSecurity=none\n");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(first.path())
+        .arg("-i").arg(second.path())
+        .arg("-o").arg(output.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    assert!(stderr.contains("open_network is left out, since it was already converted from"));
+    assert!(stderr.contains("open-copy"));
+    assert!(output.path().join("open_network.open").exists());
+    assert!(output.path().join("other_network.open").exists());
+}
+
+#[test]
+fn test_failures_out() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let failures = input.path().join("failures.tsv");
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+
+    let result = netctl2iwd()
+        .arg("--dry-run")
+        .arg(input.path().join("open"))
+        .arg(input.path().join("wired"))
+        .arg("-o").arg(output.path())
+        .arg("--failures-out").arg(&failures)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), result.status.code());
+    let contents = fs::read_to_string(&failures).unwrap();
+    let lines: Vec<_> = contents.lines().collect();
+    assert_eq!(1, lines.len());
+    assert!(lines[0].starts_with(&format!("{}\tWired (ethernet) profile", input.path().join("wired").display())), "{}", contents);
+
+    // A run without failures leaves an empty file.
+    let result = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg("-o").arg(output.path())
+        .arg("--failures-out").arg(&failures)
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    assert_eq!("", fs::read_to_string(&failures).unwrap());
+}
+
+#[test]
+fn test_only_security() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "psk", "Connection=wireless\nESSID=psk_network\nSecurity=wpa\nKey=foo_password\n");
+
+    let result = netctl2iwd()
+        .arg("-i").arg(input.path())
+        .arg("-o").arg(output.path())
+        .arg("--only-security").arg("psk")
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(0), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains("1 converted, 0 skipped (already exist), 0 failed, 1 of other security types"), "{}", stdout);
+    assert!(output.path().join("psk_network.psk").exists());
+    assert!(!output.path().join("open_network.open").exists());
+}
+
+#[test]
+fn test_log_file() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let log = input.path().join("netctl2iwd.log");
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+    fs::write(&log, "earlier run\n").unwrap();
+
+    let result = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg(input.path().join("wired"))
+        .arg(input.path().join("missing"))
+        .arg("-o").arg(output.path())
+        .arg("--log-file").arg(&log)
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), result.status.code());
+
+    let contents = fs::read_to_string(&log).unwrap();
+    let lines: Vec<_> = contents.lines().collect();
+    assert_eq!("earlier run", lines[0]);
+    // Every new line starts with a timestamp like 2021-03-14T15:09:26Z.
+    assert!(lines[1..].iter().all(|line| line.len() > 21 && line.as_bytes()[10] == b'T' && &line[19..21] == "Z "), "{}", contents);
+
+    let open = input.path().join("open").display().to_string();
+    let lines = &lines[1..];
+    let logged = |message: &str| lines.iter().any(|line| line[21..] == *message);
+    assert!(logged(&format!("{}: parsed into 1 file", open)), "{}", contents);
+    assert!(logged(&format!("{}: wrote {}", open, output.path().join("open_network.open").display())), "{}", contents);
+    assert!(logged(&format!("{}: converted", open)), "{}", contents);
+    assert!(lines.iter().any(|line| line[21..].starts_with(&format!("{}: failed: Wired", input.path().join("wired").display()))), "{}", contents);
+    assert!(lines.iter().any(|line| line[21..].starts_with(&format!("{}: failed:", input.path().join("missing").display()))), "{}", contents);
+
+    // Nothing is converted without the log.
+    let result = netctl2iwd()
+        .arg(input.path().join("open"))
+        .arg("-o").arg(output.path())
+        .arg("--log-file").arg(input.path().join("missing").join("netctl2iwd.log"))
+        .output()
+        .unwrap();
+    assert_eq!(Some(2), result.status.code());
+    assert!(String::from_utf8(result.stderr).unwrap().contains("Failed to open the log file"));
+}
+
+#[test]
+fn test_error_cause() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let looped = input.path().join("loop");
+    std::os::unix::fs::symlink(&looped, &looped).unwrap();
+
+    let result = netctl2iwd().arg(&looped).arg("-o").arg(output.path()).output().unwrap();
+    assert_eq!(Some(1), result.status.code());
+    let stdout = String::from_utf8(result.stdout).unwrap();
+    assert!(stdout.contains("Unknown error: Too many levels of symbolic links"), "{}", stdout);
+
+    for command in &["check", "list"] {
+        let result = netctl2iwd().arg(command).arg(&looped).output().unwrap();
+        let stdout = String::from_utf8(result.stdout).unwrap();
+        assert!(stdout.contains("symbolic links"), "{}", stdout);
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use netctl2iwd::convert::convert_dir;
+use netctl2iwd::convert::convert_dirs;
+use netctl2iwd::convert::Options;
+use netctl2iwd::ConversionError;
+use netctl2iwd::netctl::Warning;
+
+const OPEN_PROFILE: &str = "Connection=wireless\nESSID=open_network\nSecurity=none\n";
+const PSK_PROFILE: &str = "Connection=wireless\nESSID=psk_network\nSecurity=wpa\nKey=foo_password\n";
+const WIRED_PROFILE: &str = "Connection=ethernet\nInterface=eth0\n";
+
+fn write_profile(dir: &Path, name: &str, contents: &str) {
+    fs::write(dir.join(name), contents).unwrap();
+}
+
+fn convert(input: &Path, output: &Path) -> Vec<(String, Result<(), ConversionError>)> {
+    let mut results: Vec<_> = convert_dir(input.to_str().unwrap(), output.to_str().unwrap(), &Options::default())
+        .unwrap()
+        .into_iter()
+        .map(|(source, result)| (source, result.map(|_| ())))
+        .collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+#[test]
+fn test_convert_dir() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "open", OPEN_PROFILE);
+    write_profile(input.path(), "psk", PSK_PROFILE);
+    write_profile(input.path(), "wired", WIRED_PROFILE);
+
+    let results = convert(input.path(), output.path());
+    assert_eq!(3, results.len());
+    assert!(results[0].1.is_ok());
+    assert!(results[1].1.is_ok());
+    assert!(matches!(results[2].1, Err(ConversionError::Wired)));
+
+    let open = output.path().join("open_network.open");
+    let psk = output.path().join("psk_network.psk");
+    for path in &[&open, &psk] {
+        assert_eq!(0o600, fs::metadata(path).unwrap().permissions().mode() & 0o777);
+    }
+    assert_eq!("", fs::read_to_string(&open).unwrap());
+    let expected = "[Security]\nPassphrase=foo_password\n\
+                    PreSharedKey=55d4538ce4e578b48759e9958c936c8b632033a474105cee7e00af4a19169c54\n";
+    assert_eq!(expected, fs::read_to_string(&psk).unwrap());
+}
+
+#[test]
+fn test_convert_dir_file_exists() {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(input.path(), "psk", PSK_PROFILE);
+
+    assert!(convert(input.path(), output.path())[0].1.is_ok());
+
+    // Running again leaves the existing file alone.
+    let psk = output.path().join("psk_network.psk");
+    fs::write(&psk, "[Security]\nPassphrase=edited\n").unwrap();
+
+    let results = convert(input.path(), output.path());
+    assert!(matches!(results[0].1, Err(ConversionError::FileExists(_))));
+    assert_eq!("[Security]\nPassphrase=edited\n", fs::read_to_string(&psk).unwrap());
+}
+
+#[test]
+fn test_convert_dir_unreadable() {
+    let output = tempfile::tempdir().unwrap();
+    let missing = output.path().join("missing");
+
+    assert!(convert_dir(missing.to_str().unwrap(), output.path().to_str().unwrap(), &Options::default()).is_err());
+}
+
+#[test]
+fn test_convert_dirs_dedupe() {
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    write_profile(first.path(), "psk", PSK_PROFILE);
+    write_profile(second.path(), "psk", "Connection=wireless\nESSID=psk_network\nSecurity=wpa\nKey=other_password\n");
+    write_profile(second.path(), "open", OPEN_PROFILE);
+
+    let options = Options { dedupe: true, ..Options::default() };
+    let inputs = [first.path().to_str().unwrap(), second.path().to_str().unwrap()];
+    let results = convert_dirs(&inputs, output.path().to_str().unwrap(), &options).unwrap();
+    assert_eq!(3, results.len());
+
+    let kept = first.path().join("psk");
+    let warnings = &results.iter().find(|(source, _)| source.starts_with(second.path().to_str().unwrap()) && source.ends_with("psk"))
+        .unwrap().1.as_ref().unwrap().warnings;
+    assert!(matches!(&warnings[..], [Warning::Duplicate(ssid, source)] if ssid == "psk_network" && Path::new(source) == kept));
+
+    let psk = fs::read_to_string(output.path().join("psk_network.psk")).unwrap();
+    assert!(psk.contains("Passphrase=foo_password"));
+    assert!(output.path().join("open_network.open").exists());
+}